@@ -0,0 +1,92 @@
+use nalgebra::{vector, Vector2};
+
+use crate::{shapes::Shape, state::Scene};
+
+// scale the app starts at unless given `--pixels-per-metre`
+pub const DEFAULT_PIXELS_PER_METRE: f32 = 50.;
+
+const MIN_PIXELS_PER_METRE: f32 = 1.;
+const MAX_PIXELS_PER_METRE: f32 = 2000.;
+
+// Maps world coordinates (metres, y pointing down like the screen) to screen pixels and back.
+// `center` is the world point shown in the middle of a `viewport` sized window or frame, in pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    pub center: Vector2<f32>,
+    pub pixels_per_metre: f32,
+    pub viewport: Vector2<f32>,
+}
+
+impl Camera {
+    pub fn new(center: Vector2<f32>, pixels_per_metre: f32, viewport: Vector2<f32>) -> Self {
+        Self {
+            center,
+            pixels_per_metre: pixels_per_metre.clamp(MIN_PIXELS_PER_METRE, MAX_PIXELS_PER_METRE),
+            viewport,
+        }
+    }
+
+    // centred on everything in the scene
+    pub fn looking_at(scene: &Scene, pixels_per_metre: f32, viewport: Vector2<f32>) -> Self {
+        let center = match bounds(scene) {
+            Some((min, max)) => (min + max) / 2.,
+            None => vector![0., 0.],
+        };
+        Self::new(center, pixels_per_metre, viewport)
+    }
+
+    // centred on the scene and zoomed so that all of it fits in the viewport with a small margin
+    pub fn fitting(scene: &Scene, viewport: Vector2<f32>) -> Self {
+        let mut camera = Self::looking_at(scene, DEFAULT_PIXELS_PER_METRE, viewport);
+        if let Some((min, max)) = bounds(scene) {
+            let size = max - min;
+            let scale = (viewport.x / size.x).min(viewport.y / size.y) * 0.95;
+            if scale.is_finite() {
+                camera.pixels_per_metre = scale.clamp(MIN_PIXELS_PER_METRE, MAX_PIXELS_PER_METRE);
+            }
+        }
+        camera
+    }
+
+    fn screen_center(&self) -> Vector2<f32> {
+        self.viewport / 2.
+    }
+
+    pub fn world_to_screen(&self, point: Vector2<f32>) -> Vector2<f32> {
+        (point - self.center) * self.pixels_per_metre + self.screen_center()
+    }
+
+    pub fn screen_to_world(&self, point: Vector2<f32>) -> Vector2<f32> {
+        (point - self.screen_center()) / self.pixels_per_metre + self.center
+    }
+
+    pub fn screen_length(&self, length: f32) -> f32 {
+        length * self.pixels_per_metre
+    }
+
+    pub fn world_length(&self, length: f32) -> f32 {
+        length / self.pixels_per_metre
+    }
+
+    // moves the view so that the world follows a mouse drag of `delta` screen pixels
+    pub fn pan(&mut self, delta: Vector2<f32>) {
+        self.center -= delta / self.pixels_per_metre;
+    }
+
+    // zooms by `factor` keeping the world point under the screen point `anchor` where it is
+    pub fn zoom(&mut self, factor: f32, anchor: Vector2<f32>) {
+        let fixed = self.screen_to_world(anchor);
+        self.pixels_per_metre =
+            (self.pixels_per_metre * factor).clamp(MIN_PIXELS_PER_METRE, MAX_PIXELS_PER_METRE);
+        self.center += fixed - self.screen_to_world(anchor);
+    }
+}
+
+// box around every shape in the scene
+fn bounds(scene: &Scene) -> Option<(Vector2<f32>, Vector2<f32>)> {
+    scene
+        .shapes
+        .iter()
+        .map(Shape::aabb)
+        .reduce(|(min_a, max_a), (min_b, max_b)| (min_a.inf(&min_b), max_a.sup(&max_b)))
+}
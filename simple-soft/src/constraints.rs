@@ -1,8 +1,77 @@
 use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    joints::{PrismaticJoint, RevoluteJoint, WheelJoint},
+    shapes::Shape,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Constraint {
     Distance(DistanceConstraint),
     Spring(SpringConstraint),
     FixedPoint(FixedPointConstraint),
+    Revolute(RevoluteJoint),
+    Prismatic(PrismaticJoint),
+    Wheel(WheelJoint),
+}
+
+impl Constraint {
+    // Relative stretch of the constraint, (length - rest length) / rest length: positive when stretched and
+    // negative when compressed. Fixed point constraints and joints have no rest length so they have no strain.
+    pub fn strain(&self, shapes: &[Shape]) -> Option<f32> {
+        let (index_0, index_1, rest) = match self {
+            Constraint::Distance(c) => (c.index_0, c.index_1, c.distance),
+            Constraint::Spring(c) => (c.index_0, c.index_1, c.distance),
+            Constraint::FixedPoint(_)
+            | Constraint::Revolute(_)
+            | Constraint::Prismatic(_)
+            | Constraint::Wheel(_) => return None,
+        };
+        match (&shapes[index_0], &shapes[index_1]) {
+            (Shape::Ball(ball_0), Shape::Ball(ball_1)) if rest > 0. => {
+                let length = (ball_1.position - ball_0.position).magnitude();
+                Some((length - rest) / rest)
+            }
+            _ => None,
+        }
+    }
+
+    // pair of balls that shouldn't be collision tested against each other because of this constraint
+    pub fn ignored_pair(&self) -> Option<(usize, usize)> {
+        let (index_0, index_1) = match self {
+            Constraint::Distance(c) if !c.collide_connected => (c.index_0, c.index_1),
+            Constraint::Spring(c) if !c.collide_connected => (c.index_0, c.index_1),
+            Constraint::Revolute(c) if !c.collide_connected => (c.index_0, c.index_1?),
+            Constraint::Prismatic(c) if !c.collide_connected => (c.index_0, c.index_1?),
+            Constraint::Wheel(c) if !c.collide_connected => (c.index_0, c.index_1),
+            _ => return None,
+        };
+        Some((index_0.min(index_1), index_0.max(index_1)))
+    }
+
+    // indices of the shapes the constraint acts on
+    pub fn indices(&self) -> Vec<usize> {
+        match self {
+            Constraint::Distance(c) => vec![c.index_0, c.index_1],
+            Constraint::Spring(c) => vec![c.index_0, c.index_1],
+            Constraint::FixedPoint(c) => vec![c.index],
+            Constraint::Revolute(c) => c.indices(),
+            Constraint::Prismatic(c) => c.indices(),
+            Constraint::Wheel(c) => c.indices(),
+        }
+    }
+
+    pub fn indices_mut(&mut self) -> Vec<&mut usize> {
+        match self {
+            Constraint::Distance(c) => vec![&mut c.index_0, &mut c.index_1],
+            Constraint::Spring(c) => vec![&mut c.index_0, &mut c.index_1],
+            Constraint::FixedPoint(c) => vec![&mut c.index],
+            Constraint::Revolute(c) => c.indices_mut(),
+            Constraint::Prismatic(c) => c.indices_mut(),
+            Constraint::Wheel(c) => c.indices_mut(),
+        }
+    }
 }
 
 #[derive(Default)]
@@ -11,13 +80,18 @@ pub struct ConstraintUpdate {
     pub velocity_update: Vector2<f32>,
     pub position_update: Vector2<f32>,
     pub force_update: Vector2<f32>,
+    pub angular_velocity_update: f32,
+    pub angle_update: f32,
 }
 
-#[derive(Default)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct DistanceConstraint {
     pub index_0: usize,
     pub index_1: usize,
     pub distance: f32,
+    // whether the two balls still collide with each other
+    #[serde(default)]
+    pub collide_connected: bool,
 }
 impl DistanceConstraint {
     pub fn new(index_0: usize, index_1: usize, distance: f32) -> Self {
@@ -25,19 +99,24 @@ impl DistanceConstraint {
             index_0: index_0,
             index_1: index_1,
             distance: distance,
+            collide_connected: false,
         }
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct SpringConstraint {
     pub index_0: usize,
     pub index_1: usize,
     pub distance: f32,
     pub k: f32,
     pub dampen: f32,
+    // whether the two balls still collide with each other
+    #[serde(default)]
+    pub collide_connected: bool,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FixedPointConstraint {
     pub index: usize,
     pub position: Vector2<f32>,
@@ -0,0 +1,144 @@
+use circular_buffer::CircularBuffer;
+use macroquad::color::{Color, BLUE, GREEN, ORANGE, PURPLE, WHITE, YELLOW};
+use nalgebra::Vector2;
+
+use crate::{constraints::Constraint, shapes::Shape, state::Scene};
+
+// number of steps shown in the plots
+pub const HISTORY_LENGTH: usize = 600;
+
+// Conserved quantities of the whole scene. Potentials are measured from the origin, so only their changes
+// mean anything, and angular momentum is about the origin as well.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Measurement {
+    pub kinetic_energy: f32,
+    pub gravitational_energy: f32,
+    pub spring_energy: f32,
+    pub linear_momentum: Vector2<f32>,
+    pub angular_momentum: f32,
+}
+
+impl Measurement {
+    pub fn total_energy(&self) -> f32 {
+        self.kinetic_energy + self.gravitational_energy + self.spring_energy
+    }
+}
+
+pub fn measure(scene: &Scene) -> Measurement {
+    let mut measurement = Measurement::default();
+    for shape in &scene.shapes {
+        if let Shape::Ball(ball) = shape {
+            let momentum = ball.mass * ball.velocity;
+            measurement.kinetic_energy += ball.kinetic_energy();
+            // U = -m g . x, gravity points "downhill"
+            measurement.gravitational_energy -=
+                ball.mass * scene.settings.gravity.dot(&ball.position);
+            measurement.linear_momentum += momentum;
+            measurement.angular_momentum +=
+                ball.position.perp(&momentum) + ball.inertia() * ball.angular_velocity;
+        }
+    }
+    measurement.spring_energy = spring_energies(scene)
+        .iter()
+        .map(|(_, energy)| energy)
+        .sum();
+    measurement
+}
+
+// (constraint index, 1/2 k x^2) for every spring constraint
+pub fn spring_energies(scene: &Scene) -> Vec<(usize, f32)> {
+    let mut energies = Vec::new();
+    for (i, constraint) in scene.constraints.iter().enumerate() {
+        if let Constraint::Spring(spring) = constraint {
+            if let (Shape::Ball(ball_0), Shape::Ball(ball_1)) =
+                (&scene.shapes[spring.index_0], &scene.shapes[spring.index_1])
+            {
+                let stretch = (ball_1.position - ball_0.position).magnitude() - spring.distance;
+                energies.push((i, 0.5 * spring.k * stretch * stretch));
+            }
+        }
+    }
+    energies
+}
+
+// A rolling time series, the oldest value is dropped once it holds `HISTORY_LENGTH` values
+pub struct Series {
+    pub name: &'static str,
+    pub color: Color,
+    pub values: Box<CircularBuffer<HISTORY_LENGTH, f32>>,
+}
+
+impl Series {
+    pub fn new(name: &'static str, color: Color) -> Self {
+        Self {
+            name,
+            color,
+            values: CircularBuffer::boxed(),
+        }
+    }
+}
+
+pub struct History {
+    pub energy: [Series; 4],
+    pub momentum: [Series; 3],
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            energy: [
+                Series::new("total", WHITE),
+                Series::new("kinetic", YELLOW),
+                Series::new("gravity", BLUE),
+                Series::new("springs", GREEN),
+            ],
+            momentum: [
+                Series::new("px", ORANGE),
+                Series::new("py", PURPLE),
+                Series::new("L", WHITE),
+            ],
+        }
+    }
+
+    pub fn push(&mut self, measurement: &Measurement) {
+        let energy = [
+            measurement.total_energy(),
+            measurement.kinetic_energy,
+            measurement.gravitational_energy,
+            measurement.spring_energy,
+        ];
+        for (series, value) in self.energy.iter_mut().zip(energy) {
+            series.values.push_back(value);
+        }
+        let momentum = [
+            measurement.linear_momentum.x,
+            measurement.linear_momentum.y,
+            measurement.angular_momentum,
+        ];
+        for (series, value) in self.momentum.iter_mut().zip(momentum) {
+            series.values.push_back(value);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for series in self.energy.iter_mut().chain(self.momentum.iter_mut()) {
+            series.values.clear();
+        }
+    }
+}
+
+// smallest and largest value over all series, padded so a flat line doesn't sit on the border
+pub fn value_range(series: &[Series]) -> (f32, f32) {
+    let (min, max) = series
+        .iter()
+        .flat_map(|series| series.values.iter())
+        .filter(|value| value.is_finite())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| {
+            (min.min(*value), max.max(*value))
+        });
+    if min > max {
+        return (-1., 1.);
+    }
+    let padding = ((max - min) * 0.05).max(1e-3 * max.abs().max(1.));
+    (min - padding, max + padding)
+}
@@ -0,0 +1,442 @@
+use macroquad::prelude::*;
+use nalgebra::{vector, Vector2};
+
+use crate::{
+    camera::Camera,
+    constraints::{Constraint, DistanceConstraint, FixedPointConstraint, SpringConstraint},
+    shapes::{ball_point_collision, point_line_distance, Ball, Line, Shape},
+    state::Scene,
+    vehicle::VehicleBuilder,
+};
+
+// number of edits that can be undone
+const UNDO_LENGTH: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tool {
+    // pick a shape to edit or delete, drag to move it
+    Select,
+    // click to add a ball
+    Ball,
+    // drag to draw a wall
+    Wall,
+    // click two balls to join them with a distance constraint
+    Rod,
+    // click two balls to join them with a spring
+    Spring,
+    // click a ball to pin it where it is, or to unpin it
+    Pin,
+    // click to add a car with its chassis there
+    Vehicle,
+}
+
+impl Tool {
+    // in the order of the number keys that select them
+    const ALL: [Tool; 7] = [
+        Tool::Select,
+        Tool::Ball,
+        Tool::Wall,
+        Tool::Rod,
+        Tool::Spring,
+        Tool::Pin,
+        Tool::Vehicle,
+    ];
+    const KEYS: [KeyCode; 7] = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+    ];
+}
+
+// Edit mode for the app: the simulation is stopped and the mouse and keys change the scene instead.
+//   1-7 pick a tool (select, ball, wall, rod, spring, pin, vehicle)
+//   Delete/Backspace removes the selected shape, Z undoes the last edit, Escape drops the selection
+//   Up/Down pick a property of the selected shape and Left/Right change it
+pub struct Editor {
+    pub active: bool,
+    pub tool: Tool,
+    pub selected: Option<usize>,
+    // index into `properties` of the selected shape
+    property: usize,
+    // first ball picked for a rod or spring
+    first: Option<usize>,
+    // where the wall being drawn starts
+    wall_start: Option<Vector2<f32>>,
+    // where the shape being moved was grabbed last frame, and whether it has moved yet
+    moving_from: Option<Vector2<f32>>,
+    moved: bool,
+    // the scene before each edit
+    undo: Vec<Scene>,
+    // whether the scene changed since edit mode was entered
+    pub edited: bool,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            tool: Tool::Select,
+            selected: None,
+            property: 0,
+            first: None,
+            wall_start: None,
+            moving_from: None,
+            moved: false,
+            undo: Vec::new(),
+            edited: false,
+        }
+    }
+
+    pub fn enter(&mut self) {
+        self.active = true;
+        self.edited = false;
+        self.undo.clear();
+    }
+
+    pub fn leave(&mut self) {
+        self.active = false;
+        self.selected = None;
+        self.first = None;
+        self.wall_start = None;
+        self.moving_from = None;
+    }
+
+    // call once per frame while active
+    pub fn update(&mut self, scene: &mut Scene, camera: &Camera) {
+        for (tool, key) in Tool::ALL.iter().zip(Tool::KEYS) {
+            if is_key_pressed(key) {
+                self.tool = *tool;
+                self.first = None;
+                self.wall_start = None;
+            }
+        }
+        if is_key_pressed(KeyCode::Escape) {
+            self.selected = None;
+            self.first = None;
+            self.wall_start = None;
+        }
+        if is_key_pressed(KeyCode::Z) {
+            if let Some(previous) = self.undo.pop() {
+                *scene = previous;
+                self.selected = None;
+                self.first = None;
+            }
+        }
+        if let Some(index) = self.selected {
+            if is_key_pressed(KeyCode::Delete) || is_key_pressed(KeyCode::Backspace) {
+                self.push_undo(scene);
+                scene.remove_shape(index);
+                self.selected = None;
+                self.first = None;
+            } else {
+                self.edit_properties(scene, index);
+            }
+        }
+
+        let mpos = mouse_position();
+        let mpoint = camera.screen_to_world(vector![mpos.0, mpos.1]);
+        let pressed = is_mouse_button_pressed(MouseButton::Left);
+        match self.tool {
+            Tool::Select => {
+                if pressed {
+                    self.selected = pick(scene, &mpoint, camera.world_length(8.));
+                    self.property = 0;
+                    if self.selected.is_some() {
+                        self.moving_from = Some(mpoint);
+                        self.moved = false;
+                    }
+                }
+                if let (Some(index), Some(from)) = (self.selected, self.moving_from) {
+                    if is_mouse_button_down(MouseButton::Left) {
+                        if mpoint == from {
+                            return;
+                        }
+                        if !self.moved {
+                            // one undo step for the whole move
+                            self.push_undo(scene);
+                            self.moved = true;
+                        }
+                        match &mut scene.shapes[index] {
+                            Shape::Ball(ball) => ball.translate_by(mpoint - from),
+                            Shape::Line(line) => line.translate_by(mpoint - from),
+                        }
+                        self.moving_from = Some(mpoint);
+                    } else {
+                        self.moving_from = None;
+                    }
+                }
+            }
+            Tool::Ball => {
+                if pressed {
+                    self.push_undo(scene);
+                    scene
+                        .shapes
+                        .push(Shape::Ball(Ball::new_default().translate_to(mpoint)));
+                    self.selected = Some(scene.shapes.len() - 1);
+                }
+            }
+            Tool::Wall => {
+                if pressed {
+                    self.wall_start = Some(mpoint);
+                }
+                if is_mouse_button_released(MouseButton::Left) {
+                    if let Some(start) = self.wall_start.take() {
+                        // a click without a drag doesn't make a wall
+                        if camera.screen_length((mpoint - start).magnitude()) > 5. {
+                            self.push_undo(scene);
+                            scene.shapes.push(Shape::Line(Line::new(start, mpoint)));
+                            self.selected = Some(scene.shapes.len() - 1);
+                        }
+                    }
+                }
+            }
+            Tool::Rod | Tool::Spring => {
+                if !pressed {
+                    return;
+                }
+                let index = match pick_ball(scene, &mpoint) {
+                    Some(index) => index,
+                    None => return,
+                };
+                let first = match self.first {
+                    Some(first) if first != index => first,
+                    _ => {
+                        self.first = Some(index);
+                        return;
+                    }
+                };
+                self.first = None;
+                let distance = match (&scene.shapes[first], &scene.shapes[index]) {
+                    (Shape::Ball(ball_0), Shape::Ball(ball_1)) => {
+                        (ball_1.position - ball_0.position).magnitude()
+                    }
+                    _ => return,
+                };
+                self.push_undo(scene);
+                let constraint = if self.tool == Tool::Rod {
+                    Constraint::Distance(DistanceConstraint::new(first, index, distance))
+                } else {
+                    Constraint::Spring(SpringConstraint {
+                        index_0: first,
+                        index_1: index,
+                        distance,
+                        k: 50.,
+                        dampen: 0.1,
+                        collide_connected: false,
+                    })
+                };
+                scene.constraints.push(constraint);
+            }
+            Tool::Vehicle => {
+                if pressed {
+                    self.push_undo(scene);
+                    let vehicle = VehicleBuilder::new_default().build(scene, mpoint);
+                    self.selected = Some(scene.vehicles[vehicle].chassis);
+                }
+            }
+            Tool::Pin => {
+                if !pressed {
+                    return;
+                }
+                if let Some(index) = pick_ball(scene, &mpoint) {
+                    self.push_undo(scene);
+                    let pinned = |constraint: &Constraint| matches!(constraint, Constraint::FixedPoint(c) if c.index == index);
+                    if scene.constraints.iter().any(pinned) {
+                        scene.constraints.retain(|constraint| !pinned(constraint));
+                    } else if let Shape::Ball(ball) = &scene.shapes[index] {
+                        scene
+                            .constraints
+                            .push(Constraint::FixedPoint(FixedPointConstraint {
+                                index,
+                                position: ball.position,
+                            }));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn push_undo(&mut self, scene: &Scene) {
+        if self.undo.len() == UNDO_LENGTH {
+            self.undo.remove(0);
+        }
+        self.undo.push(scene.clone());
+        self.edited = true;
+    }
+
+    fn edit_properties(&mut self, scene: &mut Scene, index: usize) {
+        let count = properties(&scene.shapes[index]).len();
+        if is_key_pressed(KeyCode::Down) {
+            self.property = (self.property + 1) % count;
+        }
+        if is_key_pressed(KeyCode::Up) {
+            self.property = (self.property + count - 1) % count;
+        }
+        let direction = if is_key_pressed(KeyCode::Right) {
+            1.
+        } else if is_key_pressed(KeyCode::Left) {
+            -1.
+        } else {
+            return;
+        };
+        self.push_undo(scene);
+        adjust_property(&mut scene.shapes[index], self.property, direction);
+    }
+
+    // highlights, previews, constraints (including the ones that aren't drawn while simulating) and the help text
+    pub fn draw(&self, scene: &Scene, camera: &Camera) {
+        for constraint in &scene.constraints {
+            match constraint {
+                Constraint::Distance(DistanceConstraint {
+                    index_0, index_1, ..
+                })
+                | Constraint::Spring(SpringConstraint {
+                    index_0, index_1, ..
+                }) => {
+                    if let (Shape::Ball(ball_0), Shape::Ball(ball_1)) =
+                        (&scene.shapes[*index_0], &scene.shapes[*index_1])
+                    {
+                        let color = if matches!(constraint, Constraint::Spring(_)) {
+                            GREEN
+                        } else {
+                            GRAY
+                        };
+                        let start = camera.world_to_screen(ball_0.position);
+                        let end = camera.world_to_screen(ball_1.position);
+                        draw_line(start.x, start.y, end.x, end.y, 2., color);
+                    }
+                }
+                Constraint::FixedPoint(pin) => {
+                    let position = camera.world_to_screen(pin.position);
+                    draw_rectangle_lines(position.x - 5., position.y - 5., 10., 10., 2., BLUE);
+                }
+                Constraint::Revolute(_) | Constraint::Prismatic(_) | Constraint::Wheel(_) => {
+                    let anchors = match constraint {
+                        Constraint::Revolute(joint) => joint.anchors(&scene.shapes),
+                        Constraint::Prismatic(joint) => joint.anchors(&scene.shapes),
+                        Constraint::Wheel(joint) => joint.anchors(&scene.shapes),
+                        _ => None,
+                    };
+                    if let Some((anchor, _)) = anchors {
+                        let position = camera.world_to_screen(anchor);
+                        draw_circle_lines(position.x, position.y, 5., 2., BLUE);
+                    }
+                }
+            }
+        }
+        for index in self.selected.iter().chain(self.first.iter()) {
+            match &scene.shapes[*index] {
+                Shape::Ball(ball) => {
+                    let position = camera.world_to_screen(ball.position);
+                    let radius = camera.screen_length(ball.radius);
+                    draw_circle_lines(position.x, position.y, radius + 4., 2., YELLOW);
+                }
+                Shape::Line(line) => {
+                    let start = camera.world_to_screen(line.start_point);
+                    let end = camera.world_to_screen(line.end_point);
+                    draw_line(
+                        start.x,
+                        start.y,
+                        end.x,
+                        end.y,
+                        9.,
+                        Color::new(1., 1., 0., 0.5),
+                    );
+                }
+            }
+        }
+        if let Some(start) = self.wall_start {
+            let start = camera.world_to_screen(start);
+            let (x, y) = mouse_position();
+            draw_line(start.x, start.y, x, y, 5., Color::new(0., 0., 0., 0.5));
+        }
+
+        draw_text(
+            format!(
+                "EDIT {:?}  1 select 2 ball 3 wall 4 rod 5 spring 6 pin 7 car  Del delete  Z undo  Tab done",
+                self.tool
+            )
+            .as_str(),
+            100.,
+            100.,
+            20.,
+            WHITE,
+        );
+        if let Some(index) = self.selected {
+            for (i, (name, value)) in properties(&scene.shapes[index]).iter().enumerate() {
+                let marker = if i == self.property { ">" } else { " " };
+                draw_text(
+                    format!("{} {} {:.2}", marker, name, value).as_str(),
+                    100.,
+                    120. + 20. * i as f32,
+                    20.,
+                    WHITE,
+                );
+            }
+        }
+    }
+}
+
+// topmost shape under `point`, walls are picked within `tolerance` of them
+fn pick(scene: &Scene, point: &Vector2<f32>, tolerance: f32) -> Option<usize> {
+    pick_ball(scene, point).or_else(|| {
+        scene.shapes.iter().rposition(
+            |shape| matches!(shape, Shape::Line(line) if point_line_distance(line, point) < tolerance),
+        )
+    })
+}
+
+fn pick_ball(scene: &Scene, point: &Vector2<f32>) -> Option<usize> {
+    scene
+        .shapes
+        .iter()
+        .rposition(|shape| matches!(shape, Shape::Ball(ball) if ball_point_collision(ball, point, ball.radius)))
+}
+
+// editable properties of a shape and their current values
+fn properties(shape: &Shape) -> Vec<(&'static str, f32)> {
+    match shape {
+        Shape::Ball(ball) => vec![
+            ("mass", ball.mass),
+            ("radius", ball.radius),
+            ("elasticity", ball.elasticity),
+            ("friction", ball.friction),
+            (
+                "static friction",
+                ball.static_friction.unwrap_or(ball.friction),
+            ),
+        ],
+        Shape::Line(line) => vec![
+            ("elasticity", line.elasticity),
+            ("friction", line.friction),
+            (
+                "static friction",
+                line.static_friction.unwrap_or(line.friction),
+            ),
+        ],
+    }
+}
+
+// Mass and radius are scaled, coefficients are stepped by 0.05 and kept positive
+fn adjust_property(shape: &mut Shape, property: usize, direction: f32) {
+    let step = |value: f32| (value + 0.05 * direction).max(0.);
+    let scale = |value: f32| value * 1.1_f32.powf(direction);
+    match shape {
+        Shape::Ball(ball) => match property {
+            0 => ball.set_mass(scale(ball.mass)),
+            1 => ball.radius = scale(ball.radius),
+            2 => ball.elasticity = step(ball.elasticity),
+            3 => ball.friction = step(ball.friction),
+            _ => ball.static_friction = Some(step(ball.static_friction.unwrap_or(ball.friction))),
+        },
+        Shape::Line(line) => match property {
+            0 => line.elasticity = step(line.elasticity),
+            1 => line.friction = step(line.friction),
+            _ => line.static_friction = Some(step(line.static_friction.unwrap_or(line.friction))),
+        },
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    f32::consts::PI,
+    hash::{Hash, Hasher},
+};
+
+use ::rand::Rng;
+use nalgebra::{vector, Rotation2, Vector2};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    physics::Force,
+    rng::SeededRng,
+    shapes::{default_mask, Ball, Shape, DEFAULT_CATEGORY, MIN_MASS},
+    state::Scene,
+};
+
+// A random value for each particle
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Distribution {
+    Constant(f32),
+    // anywhere from the first value to the second
+    Uniform(f32, f32),
+    // cut off at 3 standard deviations from the mean
+    Normal { mean: f32, std_dev: f32 },
+}
+
+impl Distribution {
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> f32 {
+        match *self {
+            Distribution::Constant(value) => value,
+            Distribution::Uniform(min, max) if min < max => rng.gen_range(min..max),
+            Distribution::Uniform(min, _) => min,
+            Distribution::Normal { mean, std_dev } => {
+                // Box-Muller
+                let u: f32 = 1. - rng.gen::<f32>();
+                let v: f32 = rng.gen();
+                let z = (-2. * u.ln()).sqrt() * (2. * PI * v).cos();
+                mean + std_dev * z.clamp(-3., 3.)
+            }
+        }
+    }
+}
+
+// where particles appear
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum EmitterShape {
+    Point {
+        position: Vector2<f32>,
+    },
+    // anywhere along the segment
+    Line {
+        start: Vector2<f32>,
+        end: Vector2<f32>,
+    },
+    // anywhere in the box from `min` to `max`
+    Area {
+        min: Vector2<f32>,
+        max: Vector2<f32>,
+    },
+}
+
+impl EmitterShape {
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Vector2<f32> {
+        match self {
+            EmitterShape::Point { position } => *position,
+            EmitterShape::Line { start, end } => start + (end - start) * rng.gen::<f32>(),
+            EmitterShape::Area { min, max } => vector![
+                min.x + (max.x - min.x) * rng.gen::<f32>(),
+                min.y + (max.y - min.y) * rng.gen::<f32>()
+            ],
+        }
+    }
+
+    pub fn center(&self) -> Vector2<f32> {
+        match self {
+            EmitterShape::Point { position } => *position,
+            EmitterShape::Line { start, end } => (start + end) / 2.,
+            EmitterShape::Area { min, max } => (min + max) / 2.,
+        }
+    }
+}
+
+// what an emitter has done so far, cloned with the scene so rewinding and replays emit the same particles, and
+// saved with it so a scene saved mid-run carries on the same way
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmitterState {
+    // seeded from `Emitter::seed` on the first step
+    rng: Option<SeededRng>,
+    // fraction of a particle owed from earlier steps
+    pending: f32,
+    pub emitted: u32,
+}
+
+impl Hash for EmitterState {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rng.hash(state);
+        self.pending.to_bits().hash(state);
+        self.emitted.hash(state);
+    }
+}
+
+// Spawns balls at `rate` per second somewhere in `shape`, moving within `spread` radians either side of
+// `direction`. Every particle gets its own speed, radius, mass and lifetime from the distributions, drawn
+// from a generator seeded with `seed`, and copies of `forces` acting on it.
+// Particles are removed when their lifetime runs out, when they touch one of `despawn_sensors` (indices into
+// `scene.sensors`), or oldest first to make room once `max_particles` of them are alive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Emitter {
+    #[serde(default)]
+    pub name: String,
+    pub shape: EmitterShape,
+    pub rate: f32,
+    // stop after this many particles, never when `None`
+    #[serde(default)]
+    pub total: Option<u32>,
+    #[serde(default = "default_direction")]
+    pub direction: Vector2<f32>,
+    #[serde(default)]
+    pub spread: f32,
+    #[serde(default = "default_speed")]
+    pub speed: Distribution,
+    #[serde(default = "default_radius")]
+    pub radius: Distribution,
+    #[serde(default = "default_mass")]
+    pub mass: Distribution,
+    // in seconds, particles live until something else removes them when `None`
+    #[serde(default)]
+    pub lifetime: Option<Distribution>,
+    #[serde(default)]
+    pub max_particles: Option<usize>,
+    #[serde(default)]
+    pub despawn_sensors: Vec<usize>,
+    #[serde(default = "default_elasticity")]
+    pub elasticity: f32,
+    #[serde(default = "default_friction")]
+    pub friction: f32,
+    #[serde(default)]
+    pub material: Option<String>,
+    #[serde(default = "default_category")]
+    pub category: u32,
+    #[serde(default = "default_mask")]
+    pub mask: u32,
+    // emit particles of the scene's fluid
+    #[serde(default)]
+    pub fluid: bool,
+    // `entity_idx` is replaced with each particle's index
+    #[serde(default)]
+    pub forces: Vec<Force>,
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub state: EmitterState,
+}
+
+fn default_direction() -> Vector2<f32> {
+    vector![0., 1.]
+}
+
+fn default_speed() -> Distribution {
+    Distribution::Constant(0.)
+}
+
+fn default_radius() -> Distribution {
+    Distribution::Constant(0.1)
+}
+
+fn default_mass() -> Distribution {
+    Distribution::Constant(1.)
+}
+
+fn default_elasticity() -> f32 {
+    0.5
+}
+
+fn default_friction() -> f32 {
+    0.3
+}
+
+fn default_category() -> u32 {
+    DEFAULT_CATEGORY
+}
+
+impl Emitter {
+    // the next particle, `index` is the emitter's own index in `scene.emitters`
+    fn particle(&mut self, index: usize) -> Ball {
+        let rng = self
+            .state
+            .rng
+            .get_or_insert_with(|| SeededRng::new(self.seed));
+        let position = self.shape.sample(rng);
+        let angle = if self.spread > 0. {
+            rng.gen_range(-self.spread..=self.spread)
+        } else {
+            0.
+        };
+        let direction = self
+            .direction
+            .try_normalize(1e-6)
+            .unwrap_or(default_direction());
+        let velocity = Rotation2::new(angle) * direction * self.speed.sample(rng);
+        let mut ball = Ball::new(
+            position,
+            velocity,
+            vector![0., 0.],
+            vector![0., 0.],
+            self.mass.sample(rng).max(MIN_MASS),
+            self.radius.sample(rng).max(1e-3),
+            self.elasticity,
+            self.friction,
+        );
+        ball.material = self.material.clone();
+        ball.category = self.category;
+        ball.mask = self.mask;
+        ball.fluid = self.fluid;
+        ball.emitter = Some(index);
+        ball.lifetime = self.lifetime.map(|lifetime| lifetime.sample(rng));
+        ball
+    }
+}
+
+// Removes expired particles and emits new ones for a step of `dt`, called at the start of each step.
+// Returns the indices of the particles removed, in the order they were removed, each one as it was just before
+// its removal.
+pub fn update(scene: &mut Scene, dt: f32) -> Vec<usize> {
+    let mut removed = Vec::new();
+    if scene.emitters.is_empty() {
+        return removed;
+    }
+    let mut expired = Vec::new();
+    for (i, shape) in scene.shapes.iter_mut().enumerate() {
+        let Shape::Ball(ball) = shape else {
+            continue;
+        };
+        let Some(emitter) = ball.emitter.and_then(|e| scene.emitters.get(e)) else {
+            continue;
+        };
+        if let Some(lifetime) = &mut ball.lifetime {
+            *lifetime -= dt;
+        }
+        let touching = emitter.despawn_sensors.iter().any(|&sensor| {
+            scene
+                .sensors
+                .get(sensor)
+                .is_some_and(|sensor| sensor.shape.overlaps(&ball.position, ball.radius))
+        });
+        if touching || ball.lifetime.is_some_and(|lifetime| lifetime <= 0.) {
+            expired.push(i);
+        }
+    }
+    for i in expired.into_iter().rev() {
+        scene.remove_shape(i);
+        removed.push(i);
+    }
+
+    let mut emitters = std::mem::take(&mut scene.emitters);
+    for (e, emitter) in emitters.iter_mut().enumerate() {
+        emitter.state.pending += emitter.rate.max(0.) * dt;
+        let mut count = emitter.state.pending.floor();
+        emitter.state.pending -= count;
+        if let Some(total) = emitter.total {
+            count = count.min(total.saturating_sub(emitter.state.emitted) as f32);
+        }
+        for _ in 0..count as u32 {
+            if let Some(max) = emitter.max_particles {
+                // particles are appended as they are emitted, so the first one found is the oldest
+                let alive: Vec<usize> = particles(scene, e).collect();
+                if max == 0 {
+                    break;
+                }
+                if alive.len() >= max {
+                    scene.remove_shape(alive[0]);
+                    removed.push(alive[0]);
+                }
+            }
+            let index = scene.shapes.len();
+            scene.shapes.push(Shape::Ball(emitter.particle(e)));
+            for force in &emitter.forces {
+                let mut force = force.clone();
+                *force.entity_idx_mut() = index;
+                scene.forces.push(force);
+            }
+            emitter.state.emitted += 1;
+        }
+    }
+    scene.emitters = emitters;
+    removed
+}
+
+// indices of the live particles from emitter `emitter`, oldest first
+pub fn particles(scene: &Scene, emitter: usize) -> impl Iterator<Item = usize> + '_ {
+    scene
+        .shapes
+        .iter()
+        .enumerate()
+        .filter(
+            move |(_, shape)| matches!(shape, Shape::Ball(ball) if ball.emitter == Some(emitter)),
+        )
+        .map(|(i, _)| i)
+}
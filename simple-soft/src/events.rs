@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+
+use nalgebra::Vector2;
+
+use crate::{
+    physics::Collision,
+    shapes::Shape,
+    state::{index_after_removal, Scene},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContactPhase {
+    // the shapes started touching this step
+    Begin,
+    // the shapes were already touching and still are
+    Persist,
+    // the shapes stopped touching, the rest of the event is from the last step they touched
+    End,
+}
+
+// A contact between shapes `a` and `b` (indices into `scene.shapes`, `a < b`)
+#[derive(Debug, Clone, Copy)]
+pub struct ContactEvent {
+    pub phase: ContactPhase,
+    pub a: usize,
+    pub b: usize,
+    pub point: Vector2<f32>,
+    // unit normal pointing from `a` to `b`
+    pub normal: Vector2<f32>,
+    pub penetration: f32,
+    // size of the impulse the collision response applied along the normal
+    pub impulse: f32,
+}
+
+pub type ContactCallback = Box<dyn FnMut(&ContactEvent)>;
+
+// Turns the collisions of each step into begin/persist/end events. Events are passed to every subscribed
+// callback as they happen and also queued until `drain` is called.
+pub struct ContactEvents {
+    touching: BTreeMap<(usize, usize), ContactEvent>,
+    queue: Vec<ContactEvent>,
+    callbacks: Vec<ContactCallback>,
+}
+
+impl ContactEvents {
+    pub fn new() -> Self {
+        Self {
+            touching: BTreeMap::new(),
+            queue: Vec::new(),
+            callbacks: Vec::new(),
+        }
+    }
+
+    pub fn subscribe(&mut self, callback: ContactCallback) {
+        self.callbacks.push(callback);
+    }
+
+    pub fn drain(&mut self) -> std::vec::Drain<'_, ContactEvent> {
+        self.queue.drain(..)
+    }
+
+    // Forget every contact without sending end events, for when the world is replaced (reset, load, rewind)
+    pub fn clear(&mut self) {
+        self.touching.clear();
+        self.queue.clear();
+    }
+
+    // Ends the contacts of the shape at `index`, which has been removed from the scene, and shifts the later
+    // shapes down to match. The end events use the indices from before the removal.
+    pub fn remove_shape(&mut self, index: usize) {
+        let mut touching = BTreeMap::new();
+        let mut events = Vec::new();
+        for ((a, b), event) in &self.touching {
+            match (
+                index_after_removal(*a, index),
+                index_after_removal(*b, index),
+            ) {
+                (Some(a), Some(b)) => {
+                    touching.insert((a, b), ContactEvent { a, b, ..*event });
+                }
+                _ => events.push(ContactEvent {
+                    phase: ContactPhase::End,
+                    ..*event
+                }),
+            }
+        }
+        self.touching = touching;
+        for event in &events {
+            for callback in &mut self.callbacks {
+                callback(event);
+            }
+        }
+        self.queue.extend(events);
+    }
+
+    // call after each step with the collisions it returned
+    pub fn update(&mut self, scene: &Scene, collisions: &[(usize, Collision)]) {
+        let mut touching = BTreeMap::new();
+        for (i, collision) in collisions {
+            let (a, b) = (*i.min(&collision.other), *i.max(&collision.other));
+            let from_a = *i == a;
+            let event = touching.entry((a, b)).or_insert(ContactEvent {
+                phase: ContactPhase::Begin,
+                a,
+                b,
+                point: collision.point,
+                normal: if from_a {
+                    collision.normal
+                } else {
+                    -collision.normal
+                },
+                penetration: collision.depth,
+                impulse: 0.,
+            });
+            // ball-ball collisions come in as one entry per ball, use the larger impulse of the two
+            event.impulse = event
+                .impulse
+                .max(collision.impulse.dot(&collision.normal).abs());
+        }
+
+        let mut events = Vec::new();
+        for (key, old) in &self.touching {
+            if touching.contains_key(key) {
+                continue;
+            }
+            if resting(scene, key.0) && resting(scene, key.1) {
+                // pairs that are both asleep (or a sleeping ball on a wall) aren't collision tested,
+                // they are still touching
+                let mut event = *old;
+                event.phase = ContactPhase::Persist;
+                touching.insert(*key, event);
+            } else {
+                let mut event = *old;
+                event.phase = ContactPhase::End;
+                events.push(event);
+            }
+        }
+        for (key, event) in touching.iter_mut() {
+            if self.touching.contains_key(key) && event.phase == ContactPhase::Begin {
+                event.phase = ContactPhase::Persist;
+            }
+            events.push(*event);
+        }
+        self.touching = touching;
+
+        for event in &events {
+            for callback in &mut self.callbacks {
+                callback(event);
+            }
+        }
+        self.queue.extend(events);
+    }
+}
+
+fn resting(scene: &Scene, index: usize) -> bool {
+    match scene.shapes.get(index) {
+        Some(Shape::Ball(ball)) => ball.sleeping,
+        Some(Shape::Line(_)) => true,
+        None => false,
+    }
+}
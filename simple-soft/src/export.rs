@@ -0,0 +1,431 @@
+use std::{
+    error::Error,
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    shapes::Shape,
+    state::{index_after_removal, Scene},
+};
+
+// columns after `step`, `t` and `index` in each table
+const BALL_COLUMNS: [&str; 7] = ["x", "y", "vx", "vy", "fx", "fy", "kinetic_energy"];
+const CONSTRAINT_COLUMNS: [&str; 1] = ["strain"];
+
+// rows buffered per row group in the binary format
+const ROW_GROUP_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Binary,
+}
+
+impl ExportFormat {
+    // `.csv` files are written as CSV, anything else in the binary format
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => ExportFormat::Csv,
+            _ => ExportFormat::Binary,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportSettings {
+    pub path: PathBuf,
+    pub format: ExportFormat,
+    // record every `interval`th step
+    pub interval: u64,
+    // shape indices to record, all balls when `None`. Constraints are recorded when they act on any of them.
+    pub entities: Option<Vec<usize>>,
+}
+
+impl ExportSettings {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            format: ExportFormat::from_path(&path),
+            interval: 1,
+            entities: None,
+        }
+    }
+}
+
+// One table of samples: every row is (step, t, index) followed by one value per column.
+//
+// CSV tables have a header line with the column names.
+// Binary tables are columnar: a header of
+//   b"SSCOL" | version: u8 = 1 | column count: u16 | per column: name length: u16, name: utf-8
+// followed by row groups of
+//   row count: u32 | step: [u32] | t: [f32] | index: [u32] | one [f32] per column
+// with every number little endian, so a single column can be read without parsing the others.
+struct Table {
+    writer: BufWriter<File>,
+    format: ExportFormat,
+    columns: usize,
+    // bytes written so far, including anything still buffered
+    written: u64,
+    // CSV: (step, where its first row starts) for every step with rows
+    step_starts: Vec<(u64, u64)>,
+    // binary: (last step in it, where it starts) for every row group written
+    row_groups: Vec<(u64, u64)>,
+    steps: Vec<u32>,
+    times: Vec<f32>,
+    indices: Vec<u32>,
+    values: Vec<Vec<f32>>,
+}
+
+impl Table {
+    fn create<P: AsRef<Path>>(
+        path: P,
+        format: ExportFormat,
+        columns: &[&str],
+    ) -> Result<Self, Box<dyn Error>> {
+        // opened for reading too, row groups are read back when a rewind cuts one in half
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let mut writer = BufWriter::new(file);
+        match format {
+            ExportFormat::Csv => writeln!(writer, "step,t,index,{}", columns.join(","))?,
+            ExportFormat::Binary => {
+                writer.write_all(b"SSCOL")?;
+                writer.write_all(&[1])?;
+                writer.write_all(&(columns.len() as u16 + 3).to_le_bytes())?;
+                for name in ["step", "t", "index"].iter().chain(columns) {
+                    writer.write_all(&(name.len() as u16).to_le_bytes())?;
+                    writer.write_all(name.as_bytes())?;
+                }
+            }
+        }
+        let written = writer.stream_position()?;
+        Ok(Self {
+            writer,
+            format,
+            columns: columns.len(),
+            written,
+            step_starts: Vec::new(),
+            row_groups: Vec::new(),
+            steps: Vec::new(),
+            times: Vec::new(),
+            indices: Vec::new(),
+            values: vec![Vec::new(); columns.len()],
+        })
+    }
+
+    fn write_row(
+        &mut self,
+        step: u64,
+        t: f32,
+        index: usize,
+        values: &[f32],
+    ) -> Result<(), Box<dyn Error>> {
+        debug_assert_eq!(values.len(), self.columns);
+        match self.format {
+            ExportFormat::Csv => {
+                if self.step_starts.last().map(|(last, _)| *last) != Some(step) {
+                    self.step_starts.push((step, self.written));
+                }
+                let mut line = format!("{},{},{}", step, t, index);
+                for value in values {
+                    write!(line, ",{}", value)?;
+                }
+                line.push('\n');
+                self.writer.write_all(line.as_bytes())?;
+                self.written += line.len() as u64;
+            }
+            ExportFormat::Binary => {
+                self.steps.push(step as u32);
+                self.times.push(t);
+                self.indices.push(index as u32);
+                for (column, value) in self.values.iter_mut().zip(values) {
+                    column.push(*value);
+                }
+                if self.steps.len() >= ROW_GROUP_SIZE {
+                    self.write_row_group()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_row_group(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.steps.is_empty() {
+            return Ok(());
+        }
+        let last_step = *self.steps.last().unwrap() as u64;
+        self.row_groups.push((last_step, self.written));
+        self.written += 4 * (1 + self.steps.len() * (3 + self.columns)) as u64;
+        self.writer
+            .write_all(&(self.steps.len() as u32).to_le_bytes())?;
+        for step in self.steps.drain(..) {
+            self.writer.write_all(&step.to_le_bytes())?;
+        }
+        for t in self.times.drain(..) {
+            self.writer.write_all(&t.to_le_bytes())?;
+        }
+        for index in self.indices.drain(..) {
+            self.writer.write_all(&index.to_le_bytes())?;
+        }
+        for column in &mut self.values {
+            for value in column.drain(..) {
+                self.writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    // drops every row from `step` on
+    fn truncate(&mut self, step: u64) -> Result<(), Box<dyn Error>> {
+        match self.format {
+            ExportFormat::Csv => {
+                let kept = self.step_starts.partition_point(|(start, _)| *start < step);
+                if let Some(&(_, offset)) = self.step_starts.get(kept) {
+                    self.step_starts.truncate(kept);
+                    self.cut_file(offset)?;
+                }
+            }
+            ExportFormat::Binary => {
+                let kept = self.row_groups.partition_point(|(last, _)| *last < step);
+                if let Some(&(_, offset)) = self.row_groups.get(kept) {
+                    // the rows still buffered come after this row group and go, the ones in it before `step` are
+                    // read back and buffered again
+                    self.writer.flush()?;
+                    let file = self.writer.get_mut();
+                    file.seek(SeekFrom::Start(offset))?;
+                    let mut count = [0; 4];
+                    file.read_exact(&mut count)?;
+                    let count = u32::from_le_bytes(count) as usize;
+                    let mut read_column = || -> Result<Vec<[u8; 4]>, Box<dyn Error>> {
+                        let mut bytes = vec![0; 4 * count];
+                        file.read_exact(&mut bytes)?;
+                        Ok(bytes
+                            .chunks_exact(4)
+                            .map(|chunk| chunk.try_into().unwrap())
+                            .collect())
+                    };
+                    self.steps = read_column()?.into_iter().map(u32::from_le_bytes).collect();
+                    self.times = read_column()?.into_iter().map(f32::from_le_bytes).collect();
+                    self.indices = read_column()?.into_iter().map(u32::from_le_bytes).collect();
+                    for column in &mut self.values {
+                        *column = read_column()?.into_iter().map(f32::from_le_bytes).collect();
+                    }
+                    self.row_groups.truncate(kept);
+                    self.cut_file(offset)?;
+                }
+                // steps only ever go up, so the rows to keep come first
+                let rows = self.steps.partition_point(|&row| (row as u64) < step);
+                self.steps.truncate(rows);
+                self.times.truncate(rows);
+                self.indices.truncate(rows);
+                for column in &mut self.values {
+                    column.truncate(rows);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // throws away everything in the file from `offset` on and carries on writing there
+    fn cut_file(&mut self, offset: u64) -> Result<(), Box<dyn Error>> {
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+        file.set_len(offset)?;
+        file.seek(SeekFrom::Start(offset))?;
+        self.written = offset;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        self.write_row_group()?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Writes the state of every ball and the strain of every constraint to two tables, `path` and
+// `path` with `.constraints` added before the extension (e.g. run.csv and run.constraints.csv).
+pub struct Recorder {
+    settings: ExportSettings,
+    balls: Table,
+    constraints: Table,
+    // the step `record` was last called with
+    last_step: Option<u64>,
+    // `settings.entities` as they are now, after the shapes before them were removed
+    entities: Option<Vec<usize>>,
+    // every shape removed so far, with the step it was removed in (`None` until that step is recorded), so a
+    // rewind can put back the entities as they were
+    removed: Vec<(Option<u64>, usize)>,
+}
+
+impl Recorder {
+    pub fn new(settings: ExportSettings) -> Result<Self, Box<dyn Error>> {
+        let balls = Table::create(&settings.path, settings.format, &BALL_COLUMNS)?;
+        let constraints = Table::create(
+            constraints_path(&settings.path),
+            settings.format,
+            &CONSTRAINT_COLUMNS,
+        )?;
+        Ok(Self {
+            balls,
+            constraints,
+            last_step: None,
+            entities: settings.entities.clone(),
+            removed: Vec::new(),
+            settings,
+        })
+    }
+
+    // the shape at `index` has been removed, so the entities after it are one further forward
+    pub fn remove_shape(&mut self, index: usize) {
+        self.removed.push((None, index));
+        if let Some(entities) = &mut self.entities {
+            *entities = entities
+                .iter()
+                .filter_map(|&entity| index_after_removal(entity, index))
+                .collect();
+        }
+    }
+
+    fn includes(&self, index: usize) -> bool {
+        match &self.entities {
+            Some(entities) => entities.contains(&index),
+            None => true,
+        }
+    }
+
+    // call after each step, `step` is the number of steps taken so far. Steps that come again after a rewind
+    // replace the rows recorded for them the first time.
+    pub fn record(&mut self, step: u64, t: f32, scene: &Scene) -> Result<(), Box<dyn Error>> {
+        if self.last_step.is_some_and(|last| step <= last) {
+            self.truncate(step)?;
+        }
+        self.last_step = Some(step);
+        for (removed_in, _) in &mut self.removed {
+            removed_in.get_or_insert(step);
+        }
+        if !step.is_multiple_of(self.settings.interval.max(1)) {
+            return Ok(());
+        }
+        for (index, shape) in scene.shapes.iter().enumerate() {
+            if let Shape::Ball(ball) = shape {
+                if !self.includes(index) {
+                    continue;
+                }
+                let values = [
+                    ball.position.x,
+                    ball.position.y,
+                    ball.velocity.x,
+                    ball.velocity.y,
+                    ball.force.x,
+                    ball.force.y,
+                    ball.kinetic_energy(),
+                ];
+                self.balls.write_row(step, t, index, &values)?;
+            }
+        }
+        for (index, constraint) in scene.constraints.iter().enumerate() {
+            if !constraint.indices().iter().any(|&i| self.includes(i)) {
+                continue;
+            }
+            if let Some(strain) = constraint.strain(&scene.shapes) {
+                self.constraints.write_row(step, t, index, &[strain])?;
+            }
+        }
+        Ok(())
+    }
+
+    // drop everything from `step` on
+    pub fn truncate(&mut self, step: u64) -> Result<(), Box<dyn Error>> {
+        // shapes removed in the steps dropped are back, the ones removed since are still gone
+        let removed = std::mem::take(&mut self.removed);
+        self.entities = self.settings.entities.clone();
+        for (removed_in, index) in removed {
+            if removed_in.is_none_or(|removed_in| removed_in < step) {
+                self.remove_shape(index);
+                self.removed.last_mut().unwrap().0 = removed_in;
+            }
+        }
+        self.balls.truncate(step)?;
+        self.constraints.truncate(step)
+    }
+
+    pub fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        self.balls.finish()?;
+        self.constraints.finish()
+    }
+}
+
+fn constraints_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match path.extension() {
+        Some(ext) => path.with_file_name(format!("{}.constraints.{}", stem, ext.to_string_lossy())),
+        None => path.with_file_name(format!("{}.constraints", stem)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::vector;
+
+    use super::*;
+    use crate::shapes::Ball;
+
+    fn scene(xs: &[f32]) -> Scene {
+        Scene {
+            shapes: xs
+                .iter()
+                .map(|&x| Shape::Ball(Ball::new_default().translate_to(vector![x, 0.])))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    // (step, index, x) of every row in a CSV ball table
+    fn rows(path: &Path) -> Vec<(u64, usize, f32)> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let fields: Vec<&str> = line.split(',').collect();
+                (
+                    fields[0].parse().unwrap(),
+                    fields[2].parse().unwrap(),
+                    fields[3].parse().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exported_entities_follow_their_ball_past_removals() {
+        let path = std::env::temp_dir().join("simple-soft-export-removal.csv");
+        let mut settings = ExportSettings::new(&path);
+        settings.entities = Some(vec![2]);
+        let mut recorder = Recorder::new(settings).unwrap();
+        recorder.record(1, 0.1, &scene(&[1., 2., 3.])).unwrap();
+        // the first ball despawns, the exported one moves down to index 1
+        recorder.remove_shape(0);
+        recorder.record(2, 0.2, &scene(&[2., 3.])).unwrap();
+        recorder.finish().unwrap();
+        assert_eq!(rows(&path), vec![(1, 2, 3.), (2, 1, 3.)]);
+
+        // rewinding to before the removal brings the ball back at its old index
+        recorder.record(2, 0.2, &scene(&[1., 2., 3.])).unwrap();
+        recorder.finish().unwrap();
+        assert_eq!(rows(&path), vec![(1, 2, 3.), (2, 2, 3.)]);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(constraints_path(&path)).unwrap();
+    }
+}
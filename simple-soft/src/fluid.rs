@@ -0,0 +1,234 @@
+use std::{collections::HashMap, f32::consts::PI};
+
+use nalgebra::{vector, Vector2};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    shapes::{layers_collide, point_line_distance, Line, Shape},
+    state::Scene,
+};
+
+// Smoothed-particle hydrodynamics for balls with `fluid: true`. Every fluid ball is a particle of fluid: its
+// density is summed from the particles within `smoothing_length`, and the pressure that density gives pushes
+// the particles apart. Fluid balls don't collide with each other; they collide with walls and other balls
+// like any ball, so rigid balls and fluid push on each other through contacts.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FluidSettings {
+    // radius of the kernels, in metres. Particles are best spaced about half of this apart.
+    pub smoothing_length: f32,
+    // kg/m², the density the pressure pushes back towards
+    pub rest_density: f32,
+    // pressure per unit of density above `rest_density`, higher is less compressible but needs a smaller dt
+    pub stiffness: f32,
+    // kinematic viscosity in m²/s
+    pub viscosity: f32,
+    // how strongly neighbouring particles pull together, so drops and free surfaces hold together
+    pub surface_tension: f32,
+}
+
+impl Default for FluidSettings {
+    fn default() -> Self {
+        Self {
+            smoothing_length: 0.25,
+            rest_density: 100.,
+            stiffness: 200.,
+            viscosity: 0.5,
+            surface_tension: 0.5,
+        }
+    }
+}
+
+// 2D versions of the kernels from Müller et al., "Particle-Based Fluid Simulation for Interactive Applications"
+fn poly6(r: f32, h: f32) -> f32 {
+    if r >= h {
+        return 0.;
+    }
+    4. / (PI * h.powi(8)) * (h * h - r * r).powi(3)
+}
+
+// gradient of the spiky kernel for the particles `d` apart (from the neighbour to the particle), which
+// doesn't vanish as they get close so pressure keeps them apart
+fn spiky_gradient(d: Vector2<f32>, r: f32, h: f32) -> Vector2<f32> {
+    if r >= h || r < 1e-6 {
+        return vector![0., 0.];
+    }
+    -30. / (PI * h.powi(5)) * (h - r).powi(2) * d / r
+}
+
+fn viscosity_laplacian(r: f32, h: f32) -> f32 {
+    if r >= h {
+        return 0.;
+    }
+    40. / (PI * h.powi(5)) * (h - r)
+}
+
+// Buckets particles into square cells of `size` so each one only looks at the 9 cells around it
+struct Grid {
+    size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl Grid {
+    fn new(size: f32, positions: &[Vector2<f32>]) -> Self {
+        let mut grid = Self {
+            size,
+            cells: HashMap::new(),
+        };
+        for (i, position) in positions.iter().enumerate() {
+            let cell = grid.cell(position);
+            grid.cells.entry(cell).or_default().push(i);
+        }
+        grid
+    }
+
+    fn cell(&self, position: &Vector2<f32>) -> (i32, i32) {
+        (
+            (position.x / self.size).floor() as i32,
+            (position.y / self.size).floor() as i32,
+        )
+    }
+
+    // every particle in the cells around `position`, a superset of the ones within `size` of it
+    fn near<'a>(&'a self, position: &Vector2<f32>) -> impl Iterator<Item = usize> + 'a {
+        let (x, y) = self.cell(position);
+        (-1..=1)
+            .flat_map(move |dx| (-1..=1).map(move |dy| (x + dx, y + dy)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+    }
+}
+
+struct Particle {
+    index: usize,
+    position: Vector2<f32>,
+    velocity: Vector2<f32>,
+    mass: f32,
+}
+
+// Pressure, viscosity and surface tension on every fluid ball, as (index into `scene.shapes`, force).
+// Walls and rigid balls within the smoothing length count as a particle of the same mass at their nearest
+// point, which pushes particles off them before they touch and keeps the density near them from dropping off.
+// Rigid balls get the opposite of the pressure they put on the fluid, so they float or sink.
+pub fn forces(scene: &Scene) -> Vec<(usize, Vector2<f32>)> {
+    let settings = &scene.fluid;
+    let h = settings.smoothing_length.max(1e-3);
+    let particles: Vec<Particle> = scene
+        .shapes
+        .iter()
+        .enumerate()
+        .filter_map(|(index, shape)| match shape {
+            Shape::Ball(ball) if ball.fluid => Some(Particle {
+                index,
+                position: ball.position,
+                velocity: ball.velocity,
+                mass: ball.mass,
+            }),
+            _ => None,
+        })
+        .collect();
+    if particles.is_empty() {
+        return Vec::new();
+    }
+    let positions: Vec<Vector2<f32>> = particles.iter().map(|p| p.position).collect();
+    let grid = Grid::new(h, &positions);
+    let neighbours: Vec<Vec<usize>> = particles
+        .iter()
+        .map(|p| {
+            grid.near(&p.position)
+                .filter(|&j| (particles[j].position - p.position).magnitude() < h)
+                .collect()
+        })
+        .collect();
+    // (shape index, nearest point) of every wall and rigid ball within reach of each particle, among the
+    // ones it collides with
+    let boundaries: Vec<Vec<(usize, Vector2<f32>)>> = particles
+        .iter()
+        .map(|p| {
+            scene
+                .shapes
+                .iter()
+                .enumerate()
+                .filter(|(_, shape)| layers_collide(&scene.shapes[p.index], shape))
+                .filter_map(|(index, shape)| match shape {
+                    Shape::Line(line) if point_line_distance(line, &p.position) < h => {
+                        Some((index, nearest_point(line, &p.position)))
+                    }
+                    Shape::Ball(ball) if !ball.fluid => {
+                        let d = p.position - ball.position;
+                        let r = d.magnitude();
+                        (r > 1e-6 && r - ball.radius < h)
+                            .then(|| (index, ball.position + d / r * ball.radius))
+                    }
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect();
+
+    let densities: Vec<f32> = particles
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let fluid: f32 = neighbours[i]
+                .iter()
+                .map(|&j| {
+                    particles[j].mass * poly6((particles[j].position - p.position).magnitude(), h)
+                })
+                .sum();
+            let boundary: f32 = boundaries[i]
+                .iter()
+                .map(|(_, point)| p.mass * poly6((point - p.position).magnitude(), h))
+                .sum();
+            (fluid + boundary).max(1e-6)
+        })
+        .collect();
+    // no negative pressure, it would pull particles into clumps; surface tension does the pulling instead
+    let pressures: Vec<f32> = densities
+        .iter()
+        .map(|density| settings.stiffness * (density - settings.rest_density).max(0.))
+        .collect();
+
+    let mut forces = Vec::new();
+    for (i, p) in particles.iter().enumerate() {
+        let mut acceleration = vector![0., 0.];
+        for &j in &neighbours[i] {
+            if j == i {
+                continue;
+            }
+            let q = &particles[j];
+            let d = p.position - q.position;
+            let r = d.magnitude();
+            acceleration -= q.mass * (pressures[i] + pressures[j])
+                / (2. * densities[i] * densities[j])
+                * spiky_gradient(d, r, h);
+            acceleration += settings.viscosity * q.mass / densities[j]
+                * (q.velocity - p.velocity)
+                * viscosity_laplacian(r, h);
+            acceleration -= settings.surface_tension * q.mass * d * poly6(r, h);
+        }
+        for (index, point) in &boundaries[i] {
+            let d = p.position - point;
+            let r = d.magnitude();
+            let force = -p.mass * p.mass * pressures[i] / (densities[i] * densities[i])
+                * spiky_gradient(d, r, h);
+            acceleration += force / p.mass;
+            if let Shape::Ball(_) = scene.shapes[*index] {
+                forces.push((*index, -force));
+            }
+        }
+        forces.push((p.index, p.mass * acceleration));
+    }
+    forces
+}
+
+fn nearest_point(line: &Line, point: &Vector2<f32>) -> Vector2<f32> {
+    let d = line.end_point - line.start_point;
+    let length_squared = d.magnitude_squared();
+    if length_squared == 0. {
+        return line.start_point;
+    }
+    let t = ((point - line.start_point).dot(&d) / length_squared).clamp(0., 1.);
+    line.start_point + t * d
+}
@@ -0,0 +1,248 @@
+use std::{collections::VecDeque, f32::consts::PI};
+
+use ::rand::Rng;
+use nalgebra::{vector, Vector2};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    physics::Collision,
+    rng::SeededRng,
+    shapes::Shape,
+    state::{index_after_removal, Scene},
+};
+
+// Measurements for treating the balls as an ideal gas of hard disks (elasticity 1, no gravity, no friction).
+// Temperatures are in joules, with Boltzmann's constant taken as 1, so a ball of mass m at temperature T has a
+// mean kinetic energy of T and its velocity components a variance of T / m.
+
+// how thermostat walls draw velocities, cloned with the scene so rewinding and replays draw the same ones, and
+// saved with it so a scene saved mid-run carries on the same way
+#[derive(Debug, Clone, Default, Hash, Serialize, Deserialize)]
+pub struct GasSettings {
+    #[serde(default)]
+    pub seed: u64,
+    // seeded from `seed` the first time a ball hits a thermostat wall
+    #[serde(default)]
+    rng: Option<SeededRng>,
+}
+
+// Temperature of the balls from their kinetic energy relative to their centre of mass, which has 2N - 2
+// degrees of freedom for N balls. `None` for fewer than two balls.
+pub fn temperature(scene: &Scene) -> Option<f32> {
+    let balls: Vec<_> = scene
+        .shapes
+        .iter()
+        .filter_map(|shape| match shape {
+            Shape::Ball(ball) => Some(ball),
+            _ => None,
+        })
+        .collect();
+    if balls.len() < 2 {
+        return None;
+    }
+    let mass: f32 = balls.iter().map(|ball| ball.mass).sum();
+    let momentum: Vector2<f32> = balls.iter().map(|ball| ball.mass * ball.velocity).sum();
+    let center_velocity = momentum / mass;
+    let kinetic_energy: f32 = balls
+        .iter()
+        .map(|ball| 0.5 * ball.mass * (ball.velocity - center_velocity).magnitude_squared())
+        .sum();
+    Some(kinetic_energy / (balls.len() - 1) as f32)
+}
+
+// The 2D Maxwell-Boltzmann distribution of speeds for balls of `mass` at `temperature`
+pub fn maxwell_boltzmann(speed: f32, mass: f32, temperature: f32) -> f32 {
+    if temperature <= 0. {
+        return 0.;
+    }
+    let a = mass / temperature;
+    a * speed * (-0.5 * a * speed * speed).exp()
+}
+
+// Speeds of the balls counted into `bins` equal bins from 0 to `max_speed`, as (probability density,
+// Maxwell-Boltzmann density at the centre of the bin averaged over the balls' masses) for each bin
+pub fn speed_histogram(
+    scene: &Scene,
+    temperature: f32,
+    bins: usize,
+    max_speed: f32,
+) -> Vec<(f32, f32)> {
+    let mut histogram = vec![(0., 0.); bins];
+    let width = max_speed / bins as f32;
+    let mut count = 0;
+    for shape in &scene.shapes {
+        if let Shape::Ball(ball) = shape {
+            count += 1;
+            let bin = (ball.velocity.magnitude() / width) as usize;
+            if bin < bins {
+                histogram[bin].0 += 1.;
+            }
+            for (i, (_, expected)) in histogram.iter_mut().enumerate() {
+                *expected += maxwell_boltzmann((i as f32 + 0.5) * width, ball.mass, temperature);
+            }
+        }
+    }
+    if count > 0 {
+        for (density, expected) in &mut histogram {
+            *density /= count as f32 * width;
+            *expected /= count as f32;
+        }
+    }
+    histogram
+}
+
+// Gives every ball that hit a thermostat wall this substep a new velocity leaving the wall, drawn from the
+// velocities of balls crossing a surface in a gas at the wall's temperature: the component along the normal
+// with density proportional to v exp(-m v² / 2T), the one along the wall normally distributed. The collisions'
+// impulses are updated to match, so the wall's pressure still adds up.
+pub fn thermalize(scene: &mut Scene, collisions: &mut [(usize, Collision)]) {
+    for (i, collision) in collisions.iter_mut() {
+        let Some(Shape::Line(line)) = scene.shapes.get(collision.other) else {
+            continue;
+        };
+        let Some(temperature) = line.temperature else {
+            continue;
+        };
+        let rng = scene
+            .gas
+            .rng
+            .get_or_insert_with(|| SeededRng::new(scene.gas.seed));
+        let Shape::Ball(ball) = &mut scene.shapes[*i] else {
+            continue;
+        };
+        let scale = (temperature.max(0.) / ball.mass).sqrt();
+        let away = -collision.normal;
+        let along = vector![-away.y, away.x];
+        let u: f32 = 1. - rng.gen::<f32>();
+        let normal_speed = scale * (-2. * u.ln()).sqrt();
+        let velocity = normal_speed * away + scale * gaussian(rng) * along;
+        collision.impulse += ball.mass * (velocity - ball.velocity);
+        collision.vf = velocity;
+        ball.velocity = velocity;
+    }
+}
+
+// standard normal, by Box-Muller
+fn gaussian<R: Rng>(rng: &mut R) -> f32 {
+    let u: f32 = 1. - rng.gen::<f32>();
+    let v: f32 = rng.gen();
+    (-2. * u.ln()).sqrt() * (2. * PI * v).cos()
+}
+
+// Pressure on the walls, the force per metre of wall from balls hitting it averaged over the last `window`
+// seconds
+pub struct PressureGauge {
+    pub window: f32,
+    // simulated time so far, and (time, wall, impulse along the wall's normal) of every hit in the window
+    t: f32,
+    hits: VecDeque<(f32, usize, f32)>,
+}
+
+impl PressureGauge {
+    pub fn new(window: f32) -> Self {
+        Self {
+            window,
+            t: 0.,
+            hits: VecDeque::new(),
+        }
+    }
+
+    // adds the hits from a step of `dt`
+    pub fn update(&mut self, scene: &Scene, collisions: &[(usize, Collision)], dt: f32) {
+        self.t += dt;
+        for (i, collision) in collisions {
+            if let (Some(Shape::Ball(_)), Some(Shape::Line(_))) =
+                (scene.shapes.get(*i), scene.shapes.get(collision.other))
+            {
+                // the ball's change in momentum points away from the wall, the wall gets the opposite
+                let impulse = -collision.impulse.dot(&collision.normal);
+                self.hits.push_back((self.t, collision.other, impulse));
+            }
+        }
+        while self
+            .hits
+            .front()
+            .is_some_and(|(t, _, _)| *t <= self.t - self.window)
+        {
+            self.hits.pop_front();
+        }
+    }
+
+    // drops the hits on the shape at `index`, which has been removed, and shifts the later walls down to match
+    pub fn remove_shape(&mut self, index: usize) {
+        self.hits = self
+            .hits
+            .iter()
+            .filter_map(|(t, wall, impulse)| {
+                index_after_removal(*wall, index).map(|wall| (*t, wall, *impulse))
+            })
+            .collect();
+    }
+
+    // forget every hit, for when the world is replaced (reset, load, rewind)
+    pub fn clear(&mut self) {
+        self.t = 0.;
+        self.hits.clear();
+    }
+
+    // time the hits were collected over, the whole window once it has run that long
+    fn span(&self) -> f32 {
+        self.t.min(self.window)
+    }
+
+    // (index into `scene.shapes`, pressure) for every wall
+    pub fn pressures(&self, scene: &Scene) -> Vec<(usize, f32)> {
+        scene
+            .shapes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, shape)| match shape {
+                Shape::Line(line) => {
+                    let impulse: f32 = self
+                        .hits
+                        .iter()
+                        .filter(|(_, wall, _)| *wall == index)
+                        .map(|(_, _, impulse)| impulse)
+                        .sum();
+                    let length = (line.end_point - line.start_point).magnitude();
+                    (self.span() > 0. && length > 0.)
+                        .then(|| (index, impulse / (self.span() * length)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    // pressure over all the walls together
+    pub fn pressure(&self, scene: &Scene) -> Option<f32> {
+        let length: f32 = scene
+            .shapes
+            .iter()
+            .filter_map(|shape| match shape {
+                Shape::Line(line) => Some((line.end_point - line.start_point).magnitude()),
+                _ => None,
+            })
+            .sum();
+        let impulse: f32 = self.hits.iter().map(|(_, _, impulse)| impulse).sum();
+        (self.span() > 0. && length > 0.).then(|| impulse / (self.span() * length))
+    }
+}
+
+// one line with the temperature, number of balls and pressure, for the overlay and `--log-gas`
+pub fn summary(scene: &Scene, gauge: &PressureGauge) -> String {
+    let balls = scene
+        .shapes
+        .iter()
+        .filter(|shape| matches!(shape, Shape::Ball(_)))
+        .count();
+    let temperature = temperature(scene).unwrap_or(0.);
+    let pressure = gauge.pressure(scene).unwrap_or(0.);
+    format!("T {:.3} J  N {}  P {:.3} N/m", temperature, balls, pressure)
+}
+
+// prints the summary for `--log-gas` when a step of `dt` from `t` crosses a whole simulated second
+pub fn log_if_second_elapsed(t: f32, dt: f32, scene: &Scene, gauge: &PressureGauge) {
+    if (t + dt).floor() > t.floor() {
+        println!("t {:.2} s  {}", t + dt, summary(scene, gauge));
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use color_quant::NeuQuant;
+
+use crate::raster::Canvas;
+
+// codes 0-255 are palette indices, then come the clear and end codes and the table entries
+const CLEAR_CODE: u16 = 256;
+const END_CODE: u16 = 257;
+const FIRST_ENTRY: u16 = 258;
+const MAX_CODE_WIDTH: u32 = 12;
+
+// Writes frames as a looping animated GIF89a. Every frame gets its own 256 colour palette, picked by
+// NeuQuant from its pixels.
+pub struct GifWriter {
+    writer: BufWriter<File>,
+    width: u16,
+    height: u16,
+}
+
+impl GifWriter {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (width, height) = (u16::try_from(width)?, u16::try_from(height)?);
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(b"GIF89a")?;
+        // logical screen: no global colour table, the frames have their own
+        writer.write_all(&width.to_le_bytes())?;
+        writer.write_all(&height.to_le_bytes())?;
+        writer.write_all(&[0, 0, 0])?;
+        // loop forever
+        writer.write_all(&[0x21, 0xff, 11])?;
+        writer.write_all(b"NETSCAPE2.0")?;
+        writer.write_all(&[3, 1, 0, 0, 0])?;
+        Ok(Self {
+            writer,
+            width,
+            height,
+        })
+    }
+
+    // `delay` is how long the frame is shown, in seconds
+    pub fn add_frame(&mut self, canvas: &Canvas, delay: f32) -> Result<(), Box<dyn Error>> {
+        if canvas.width != self.width as u32 || canvas.height != self.height as u32 {
+            return Err("frame size doesn't match the animation".into());
+        }
+        let quant = NeuQuant::new(10, 256, &canvas.pixels);
+        let mut palette = quant.color_map_rgb();
+        palette.resize(3 * 256, 0);
+        // frames are mostly a handful of flat colours, so most lookups are repeats
+        let mut lookup: HashMap<&[u8], u8> = HashMap::new();
+        let indices: Vec<u8> = canvas
+            .pixels
+            .chunks_exact(4)
+            .map(|pixel| {
+                *lookup
+                    .entry(pixel)
+                    .or_insert_with(|| quant.index_of(pixel) as u8)
+            })
+            .collect();
+
+        // graphic control: keep the frame when the next one is drawn, delay in hundredths of a second.
+        // Most viewers show anything under 2 as 10, so that is the shortest delay.
+        let delay = ((delay * 100.).round() as u16).max(2);
+        self.writer.write_all(&[0x21, 0xf9, 4, 0b100])?;
+        self.writer.write_all(&delay.to_le_bytes())?;
+        self.writer.write_all(&[0, 0])?;
+        // image descriptor covering the whole screen with a 256 entry local colour table
+        self.writer.write_all(&[0x2c, 0, 0, 0, 0])?;
+        self.writer.write_all(&self.width.to_le_bytes())?;
+        self.writer.write_all(&self.height.to_le_bytes())?;
+        self.writer.write_all(&[0x87])?;
+        self.writer.write_all(&palette)?;
+
+        self.writer.write_all(&[8])?;
+        for block in lzw_compress(&indices).chunks(255) {
+            self.writer.write_all(&[block.len() as u8])?;
+            self.writer.write_all(block)?;
+        }
+        self.writer.write_all(&[0])?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        self.writer.write_all(&[0x3b])?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// codes of varying width packed least significant bit first
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, width: u32) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += width;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+// GIF's variant of LZW with 8 bit symbols: codes start 9 bits wide and grow up to 12, after which the
+// table is cleared and started again
+fn lzw_compress(indices: &[u8]) -> Vec<u8> {
+    let mut output = BitWriter {
+        bytes: Vec::new(),
+        buffer: 0,
+        bits: 0,
+    };
+    let mut width = 9;
+    output.write(CLEAR_CODE, width);
+    let Some((&first, rest)) = indices.split_first() else {
+        output.write(END_CODE, width);
+        return output.finish();
+    };
+
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = FIRST_ENTRY;
+    let mut prefix = first as u16;
+    for &index in rest {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        output.write(prefix, width);
+        if next < 1 << MAX_CODE_WIDTH {
+            table.insert((prefix, index), next);
+            next += 1;
+            // the decoder adds each entry one code later, so the width grows once the entry past the
+            // current width has been added
+            if next > 1 << width && width < MAX_CODE_WIDTH {
+                width += 1;
+            }
+        } else {
+            output.write(CLEAR_CODE, width);
+            table.clear();
+            next = FIRST_ENTRY;
+            width = 9;
+        }
+        prefix = index as u16;
+    }
+    output.write(prefix, width);
+    output.write(END_CODE, width);
+    output.finish()
+}
@@ -0,0 +1,224 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use macroquad::color::RED;
+use nalgebra::vector;
+
+use crate::{
+    camera::Camera,
+    export::Recorder,
+    gas::{self, PressureGauge},
+    gif::GifWriter,
+    langevin::MsdRecorder,
+    raster::RasterRenderer,
+    renderer::Renderer,
+    sensors::SensorEvents,
+    solver::RungeKuttaIntegrator,
+    state::Scene,
+    svg::SvgRenderer,
+    world,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameFormat {
+    Svg,
+    // rasterised on the CPU, as are GIF frames
+    Png,
+    Gif,
+}
+
+// where and how often frames of a headless run are drawn
+#[derive(Debug, Clone)]
+pub struct FrameSettings {
+    // SVG and PNG frames are written to this directory as frame-00000.svg, frame-00001.svg, ...,
+    // a GIF is this file
+    pub path: PathBuf,
+    pub format: FrameFormat,
+    // draw every `interval`th step, the starting world is always drawn
+    pub interval: u64,
+    // frame size in pixels
+    pub width: u32,
+    pub height: u32,
+    // zoomed to fit the whole scene when `None`
+    pub pixels_per_metre: Option<f32>,
+    // how long a GIF shows each frame in seconds, in real time (`interval` steps of dt) when `None`
+    pub frame_delay: Option<f32>,
+}
+
+impl FrameSettings {
+    pub fn new<P: AsRef<Path>>(path: P, format: FrameFormat) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            format,
+            interval: 1,
+            width: 800,
+            height: 800,
+            pixels_per_metre: None,
+            frame_delay: None,
+        }
+    }
+}
+
+// parses `800x600`
+pub fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let (width, height) = size.split_once('x')?;
+    Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+}
+
+// draws the frames of a run and writes them out
+struct FrameWriter {
+    settings: FrameSettings,
+    camera: Camera,
+    gif: Option<GifWriter>,
+    frame_delay: f32,
+    count: u64,
+}
+
+impl FrameWriter {
+    fn create(settings: FrameSettings, scene: &Scene) -> Result<Self, Box<dyn Error>> {
+        let viewport = vector![settings.width as f32, settings.height as f32];
+        let camera = match settings.pixels_per_metre {
+            Some(pixels_per_metre) => Camera::looking_at(scene, pixels_per_metre, viewport),
+            None => Camera::fitting(scene, viewport),
+        };
+        let gif = match settings.format {
+            FrameFormat::Gif => Some(GifWriter::create(
+                &settings.path,
+                settings.width,
+                settings.height,
+            )?),
+            FrameFormat::Svg | FrameFormat::Png => {
+                fs::create_dir_all(&settings.path)?;
+                None
+            }
+        };
+        let frame_delay = settings
+            .frame_delay
+            .unwrap_or(scene.settings.dt * settings.interval.max(1) as f32);
+        Ok(Self {
+            settings,
+            camera,
+            gif,
+            frame_delay,
+            count: 0,
+        })
+    }
+
+    fn frame_path(&self, extension: &str) -> PathBuf {
+        self.settings
+            .path
+            .join(format!("frame-{:05}.{}", self.count, extension))
+    }
+
+    fn write(&mut self, scene: &Scene, sensor_events: &SensorEvents) -> Result<(), Box<dyn Error>> {
+        // the same red the app clears the window with
+        match self.settings.format {
+            FrameFormat::Svg => {
+                let mut renderer = SvgRenderer::new(self.camera, RED);
+                renderer.scene(scene, sensor_events);
+                renderer.save(self.frame_path("svg"))?;
+            }
+            FrameFormat::Png | FrameFormat::Gif => {
+                let mut renderer = RasterRenderer::new(self.camera, RED);
+                renderer.scene(scene, sensor_events);
+                match &mut self.gif {
+                    Some(gif) => gif.add_frame(&renderer.canvas, self.frame_delay)?,
+                    None => renderer.canvas.save_png(self.frame_path("png"))?,
+                }
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        if let Some(gif) = self.gif {
+            gif.finish()?;
+        }
+        println!(
+            "wrote {} frames to {}",
+            self.count,
+            self.settings.path.display()
+        );
+        Ok(())
+    }
+}
+
+// Runs `steps` steps of `scene` without opening a window, drawing frames and exporting states if asked to.
+// The camera looks at the scene as it was at the start and stays there. `msd` records the mean squared
+// displacement of the thermostatted balls, and `log_gas` prints the gas temperature and pressure every simulated
+// second.
+pub fn run(
+    mut scene: Scene,
+    steps: u64,
+    frames: Option<FrameSettings>,
+    mut exporter: Option<Recorder>,
+    mut msd: Option<MsdRecorder>,
+    log_gas: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut frames = frames
+        .map(|settings| FrameWriter::create(settings, &scene))
+        .transpose()?;
+
+    let integrator = RungeKuttaIntegrator::new(scene.settings.dt);
+    let mut sensor_events = SensorEvents::new();
+    let mut gauge = PressureGauge::new(1.);
+    let mut t = 0.;
+    if let Some(frames) = &mut frames {
+        frames.write(&scene, &sensor_events)?;
+    }
+    for step in 1..=steps {
+        let output = world::step(&mut scene, &integrator, t);
+        for &index in &output.removed {
+            sensor_events.remove_shape(&scene, index);
+            gauge.remove_shape(index);
+            if let Some(recorder) = &mut exporter {
+                recorder.remove_shape(index);
+            }
+            if let Some(recorder) = &mut msd {
+                recorder.remove_shape(index);
+            }
+        }
+        sensor_events.update(&scene);
+        sensor_events.drain();
+        gauge.update(&scene, &output.collisions, integrator.dt());
+        if log_gas {
+            gas::log_if_second_elapsed(t, integrator.dt(), &scene, &gauge);
+        }
+        t += integrator.dt();
+
+        if let Some(recorder) = &mut exporter {
+            recorder.record(step, t, &scene)?;
+        }
+        if let Some(recorder) = &mut msd {
+            recorder.record(t, &scene)?;
+        }
+        if let Some(frames) = &mut frames {
+            if step % frames.settings.interval.max(1) == 0 {
+                frames.write(&scene, &sensor_events)?;
+            }
+        }
+    }
+    if let Some(recorder) = &mut exporter {
+        recorder.finish()?;
+    }
+    if let Some(frames) = frames {
+        frames.finish()?;
+    }
+    if let Some(recorder) = msd {
+        for summary in recorder.finish()? {
+            print!(
+                "thermostat {}: mean squared displacement {:.4} m², diffusion coefficient {:.4} m²/s",
+                summary.thermostat, summary.msd, summary.diffusion
+            );
+            match summary.expected {
+                Some(expected) => println!(" (T / m γ = {:.4})", expected),
+                None => println!(),
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,457 @@
+use nalgebra::{vector, Matrix2, Rotation2, Vector2};
+use serde::{Deserialize, Serialize};
+
+use crate::{constraints::ConstraintUpdate, shapes::Shape};
+
+// Drives a joint towards a relative speed: rad/s and at most `max_force` N·m of torque for revolute joints,
+// m/s and at most `max_force` N for prismatic ones
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Motor {
+    pub speed: f32,
+    pub max_force: f32,
+}
+
+// Pins `anchor_0` on ball `index_0` to `anchor_1` on ball `index_1`, leaving them free to turn about the pin.
+// Anchors are in each ball's own frame and turn with it. With no `index_1` the ball is pinned to the world
+// and `anchor_1` is a point in the world.
+// `limits` bound the relative angle, ball 0's angle minus ball 1's (0 for the world), in radians, and the
+// motor drives that angle's rate of change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevoluteJoint {
+    pub index_0: usize,
+    #[serde(default)]
+    pub index_1: Option<usize>,
+    #[serde(default)]
+    pub anchor_0: Vector2<f32>,
+    #[serde(default)]
+    pub anchor_1: Vector2<f32>,
+    #[serde(default)]
+    pub limits: Option<(f32, f32)>,
+    #[serde(default)]
+    pub motor: Option<Motor>,
+    // whether the two balls still collide with each other
+    #[serde(default)]
+    pub collide_connected: bool,
+}
+
+// Lets `anchor_0` on ball `index_0` slide along `axis` through `anchor_1` on ball `index_1` (or the world)
+// and stops the two from turning relative to each other. `axis` is in ball 1's frame, or the world's.
+// `limits` bound how far along the axis anchor 0 is from anchor 1, and the motor drives its speed along it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrismaticJoint {
+    pub index_0: usize,
+    #[serde(default)]
+    pub index_1: Option<usize>,
+    #[serde(default)]
+    pub anchor_0: Vector2<f32>,
+    #[serde(default)]
+    pub anchor_1: Vector2<f32>,
+    pub axis: Vector2<f32>,
+    #[serde(default)]
+    pub limits: Option<(f32, f32)>,
+    #[serde(default)]
+    pub motor: Option<Motor>,
+    #[serde(default)]
+    pub collide_connected: bool,
+}
+
+// Holds ball `index_0`, the wheel, on the line through `anchor_1` along `axis` on ball `index_1`, the chassis
+// (both in the chassis's frame), and pulls it back to `anchor_1` with a spring-damper like a car's suspension.
+// The wheel turns freely and the motor drives how fast it turns relative to the chassis. `limits` bound the
+// suspension travel, how far the wheel's centre is from `anchor_1` along the axis.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WheelJoint {
+    pub index_0: usize,
+    pub index_1: usize,
+    #[serde(default)]
+    pub anchor_1: Vector2<f32>,
+    pub axis: Vector2<f32>,
+    // N/m and N·s/m
+    pub stiffness: f32,
+    #[serde(default)]
+    pub damping: f32,
+    #[serde(default)]
+    pub limits: Option<(f32, f32)>,
+    #[serde(default)]
+    pub motor: Option<Motor>,
+    #[serde(default)]
+    pub collide_connected: bool,
+}
+
+// A ball's state while a joint is solved, or the world's: fixed, with no inverse mass
+#[derive(Debug, Clone, Copy)]
+struct Body {
+    position: Vector2<f32>,
+    velocity: Vector2<f32>,
+    angle: f32,
+    angular_velocity: f32,
+    inverse_mass: f32,
+    inverse_inertia: f32,
+}
+
+impl Body {
+    fn of(shapes: &[Shape], index: Option<usize>) -> Option<Self> {
+        match index.map(|index| &shapes[index]) {
+            None => Some(Self {
+                position: vector![0., 0.],
+                velocity: vector![0., 0.],
+                angle: 0.,
+                angular_velocity: 0.,
+                inverse_mass: 0.,
+                inverse_inertia: 0.,
+            }),
+            Some(Shape::Ball(ball)) => Some(Self {
+                position: ball.position,
+                velocity: ball.velocity,
+                angle: ball.angle,
+                angular_velocity: ball.angular_velocity,
+                inverse_mass: 1. / ball.mass,
+                inverse_inertia: 1. / ball.inertia(),
+            }),
+            Some(Shape::Line(_)) => None,
+        }
+    }
+
+    // `local` turned with the body, measured from its centre
+    fn arm(&self, local: Vector2<f32>) -> Vector2<f32> {
+        Rotation2::new(self.angle) * local
+    }
+
+    // velocity of the point `arm` from the centre
+    fn point_velocity(&self, arm: Vector2<f32>) -> Vector2<f32> {
+        self.velocity + self.angular_velocity * vector![-arm.y, arm.x]
+    }
+
+    fn apply_impulse(&mut self, impulse: Vector2<f32>, arm: Vector2<f32>) {
+        self.velocity += self.inverse_mass * impulse;
+        self.angular_velocity += self.inverse_inertia * arm.perp(&impulse);
+    }
+
+    // the same as an impulse, but moving the body instead of changing its velocity
+    fn shift(&mut self, correction: Vector2<f32>, arm: Vector2<f32>) {
+        self.position += self.inverse_mass * correction;
+        self.angle += self.inverse_inertia * arm.perp(&correction);
+    }
+
+    fn update(&self, before: &Body, index: usize) -> ConstraintUpdate {
+        ConstraintUpdate {
+            index,
+            velocity_update: self.velocity - before.velocity,
+            position_update: self.position - before.position,
+            angular_velocity_update: self.angular_velocity - before.angular_velocity,
+            angle_update: self.angle - before.angle,
+            ..Default::default()
+        }
+    }
+}
+
+// inverse of the effective mass of two bodies along `direction` at the two arms
+fn effective_mass(
+    a: &Body,
+    arm_a: Vector2<f32>,
+    b: &Body,
+    arm_b: Vector2<f32>,
+    n: Vector2<f32>,
+) -> f32 {
+    a.inverse_mass
+        + b.inverse_mass
+        + a.inverse_inertia * arm_a.perp(&n).powi(2)
+        + b.inverse_inertia * arm_b.perp(&n).powi(2)
+}
+
+// Angular impulse towards relative angular velocity `speed`, capped at `max_torque` over the step
+fn drive_rotation(a: &mut Body, b: &mut Body, speed: f32, max_torque: f32, dt: f32) {
+    let k = a.inverse_inertia + b.inverse_inertia;
+    if k > 0. {
+        let relative = a.angular_velocity - b.angular_velocity;
+        let limit = max_torque.abs() * dt;
+        let impulse = (-(relative - speed) / k).clamp(-limit, limit);
+        a.angular_velocity += a.inverse_inertia * impulse;
+        b.angular_velocity -= b.inverse_inertia * impulse;
+    }
+}
+
+// stops the relative angle going further past `lower` or `upper` and moves it back inside
+fn limit_rotation(a: &mut Body, b: &mut Body, lower: f32, upper: f32) {
+    let k = a.inverse_inertia + b.inverse_inertia;
+    if k <= 0. {
+        return;
+    }
+    let angle = a.angle - b.angle;
+    let relative = a.angular_velocity - b.angular_velocity;
+    if (angle <= lower && relative < 0.) || (angle >= upper && relative > 0.) {
+        a.angular_velocity -= a.inverse_inertia * relative / k;
+        b.angular_velocity += b.inverse_inertia * relative / k;
+    }
+    let error = if angle < lower {
+        lower - angle
+    } else if angle > upper {
+        upper - angle
+    } else {
+        0.
+    };
+    a.angle += a.inverse_inertia * error / k;
+    b.angle -= b.inverse_inertia * error / k;
+}
+
+// stops anchor 0 going further along `direction` from anchor 1 than `lower` or `upper`, where it is
+// `translation` along it now, and moves it back inside
+fn limit_translation(
+    a: &mut Body,
+    arm_a: Vector2<f32>,
+    b: &mut Body,
+    arm_b: Vector2<f32>,
+    direction: Vector2<f32>,
+    translation: f32,
+    (lower, upper): (f32, f32),
+) {
+    let k = effective_mass(a, arm_a, b, arm_b, direction);
+    if k <= 0. {
+        return;
+    }
+    let speed = (a.point_velocity(arm_a) - b.point_velocity(arm_b)).dot(&direction);
+    if (translation <= lower && speed < 0.) || (translation >= upper && speed > 0.) {
+        a.apply_impulse(-speed / k * direction, arm_a);
+        b.apply_impulse(speed / k * direction, arm_b);
+    }
+    let error = if translation < lower {
+        lower - translation
+    } else if translation > upper {
+        upper - translation
+    } else {
+        0.
+    };
+    a.shift(error / k * direction, arm_a);
+    b.shift(-error / k * direction, arm_b);
+}
+
+// no relative motion of the anchors along `direction`, and moves them `error` back towards each other
+fn lock_translation(
+    a: &mut Body,
+    arm_a: Vector2<f32>,
+    b: &mut Body,
+    arm_b: Vector2<f32>,
+    direction: Vector2<f32>,
+    error: f32,
+) {
+    let k = effective_mass(a, arm_a, b, arm_b, direction);
+    if k <= 0. {
+        return;
+    }
+    let speed = (a.point_velocity(arm_a) - b.point_velocity(arm_b)).dot(&direction);
+    a.apply_impulse(-speed / k * direction, arm_a);
+    b.apply_impulse(speed / k * direction, arm_b);
+    a.shift(-error / k * direction, arm_a);
+    b.shift(error / k * direction, arm_b);
+}
+
+impl RevoluteJoint {
+    pub fn indices(&self) -> Vec<usize> {
+        std::iter::once(self.index_0).chain(self.index_1).collect()
+    }
+
+    pub fn indices_mut(&mut self) -> Vec<&mut usize> {
+        std::iter::once(&mut self.index_0)
+            .chain(self.index_1.as_mut())
+            .collect()
+    }
+
+    // where the two anchors are in the world, together once the joint is satisfied
+    pub fn anchors(&self, shapes: &[Shape]) -> Option<(Vector2<f32>, Vector2<f32>)> {
+        let a = Body::of(shapes, Some(self.index_0))?;
+        let b = Body::of(shapes, self.index_1)?;
+        Some((
+            a.position + a.arm(self.anchor_0),
+            b.position + b.arm(self.anchor_1),
+        ))
+    }
+
+    pub fn solve(&self, shapes: &[Shape], dt: f32) -> Vec<ConstraintUpdate> {
+        let (Some(before_a), Some(before_b)) = (
+            Body::of(shapes, Some(self.index_0)),
+            Body::of(shapes, self.index_1),
+        ) else {
+            return Vec::new();
+        };
+        let (mut a, mut b) = (before_a, before_b);
+
+        // the motor and limits first so that the pin, solved last, wins
+        if let Some(motor) = self.motor {
+            drive_rotation(&mut a, &mut b, motor.speed, motor.max_force, dt);
+        }
+        if let Some((lower, upper)) = self.limits {
+            limit_rotation(&mut a, &mut b, lower, upper);
+        }
+
+        // the anchors move together
+        let (arm_a, arm_b) = (a.arm(self.anchor_0), b.arm(self.anchor_1));
+        let relative = a.point_velocity(arm_a) - b.point_velocity(arm_b);
+        if let Some(impulse) = point_impulse(&a, arm_a, &b, arm_b, relative) {
+            a.apply_impulse(impulse, arm_a);
+            b.apply_impulse(-impulse, arm_b);
+        }
+
+        // and sit on top of each other
+        let (arm_a, arm_b) = (a.arm(self.anchor_0), b.arm(self.anchor_1));
+        let error = (a.position + arm_a) - (b.position + arm_b);
+        if let Some(correction) = point_impulse(&a, arm_a, &b, arm_b, error) {
+            a.shift(correction, arm_a);
+            b.shift(-correction, arm_b);
+        }
+
+        let mut updates = vec![a.update(&before_a, self.index_0)];
+        updates.extend(self.index_1.map(|index| b.update(&before_b, index)));
+        updates
+    }
+}
+
+// The impulse on body `a` (and the opposite on `b`) that cancels the relative motion `error` of the two
+// anchor points, `None` when neither body can move
+fn point_impulse(
+    a: &Body,
+    arm_a: Vector2<f32>,
+    b: &Body,
+    arm_b: Vector2<f32>,
+    error: Vector2<f32>,
+) -> Option<Vector2<f32>> {
+    let mass = a.inverse_mass + b.inverse_mass;
+    let (ia, ib) = (a.inverse_inertia, b.inverse_inertia);
+    let k = Matrix2::new(
+        mass + ia * arm_a.y * arm_a.y + ib * arm_b.y * arm_b.y,
+        -ia * arm_a.x * arm_a.y - ib * arm_b.x * arm_b.y,
+        -ia * arm_a.x * arm_a.y - ib * arm_b.x * arm_b.y,
+        mass + ia * arm_a.x * arm_a.x + ib * arm_b.x * arm_b.x,
+    );
+    k.try_inverse().map(|inverse| -(inverse * error))
+}
+
+impl PrismaticJoint {
+    pub fn indices(&self) -> Vec<usize> {
+        std::iter::once(self.index_0).chain(self.index_1).collect()
+    }
+
+    pub fn indices_mut(&mut self) -> Vec<&mut usize> {
+        std::iter::once(&mut self.index_0)
+            .chain(self.index_1.as_mut())
+            .collect()
+    }
+
+    pub fn anchors(&self, shapes: &[Shape]) -> Option<(Vector2<f32>, Vector2<f32>)> {
+        let a = Body::of(shapes, Some(self.index_0))?;
+        let b = Body::of(shapes, self.index_1)?;
+        Some((
+            a.position + a.arm(self.anchor_0),
+            b.position + b.arm(self.anchor_1),
+        ))
+    }
+
+    // the axis in the world
+    pub fn world_axis(&self, shapes: &[Shape]) -> Option<Vector2<f32>> {
+        let b = Body::of(shapes, self.index_1)?;
+        Some(b.arm(self.axis.try_normalize(1e-6)?))
+    }
+
+    pub fn solve(&self, shapes: &[Shape], dt: f32) -> Vec<ConstraintUpdate> {
+        let (Some(before_a), Some(before_b)) = (
+            Body::of(shapes, Some(self.index_0)),
+            Body::of(shapes, self.index_1),
+        ) else {
+            return Vec::new();
+        };
+        let Some(local_axis) = self.axis.try_normalize(1e-6) else {
+            return Vec::new();
+        };
+        let (mut a, mut b) = (before_a, before_b);
+
+        // no turning relative to each other
+        drive_rotation(&mut a, &mut b, 0., f32::INFINITY, dt);
+        limit_rotation(&mut a, &mut b, 0., 0.);
+
+        let axis = b.arm(local_axis);
+        let normal = vector![-axis.y, axis.x];
+        let (arm_a, arm_b) = (a.arm(self.anchor_0), b.arm(self.anchor_1));
+        let along = effective_mass(&a, arm_a, &b, arm_b, axis);
+        let offset = (a.position + arm_a) - (b.position + arm_b);
+        let translation = offset.dot(&axis);
+
+        if along > 0. {
+            if let Some(motor) = self.motor {
+                let speed = (a.point_velocity(arm_a) - b.point_velocity(arm_b)).dot(&axis);
+                let limit = motor.max_force.abs() * dt;
+                let impulse = (-(speed - motor.speed) / along).clamp(-limit, limit);
+                a.apply_impulse(impulse * axis, arm_a);
+                b.apply_impulse(-impulse * axis, arm_b);
+            }
+            if let Some(limits) = self.limits {
+                limit_translation(&mut a, arm_a, &mut b, arm_b, axis, translation, limits);
+            }
+        }
+
+        // nothing across the axis
+        lock_translation(&mut a, arm_a, &mut b, arm_b, normal, offset.dot(&normal));
+
+        let mut updates = vec![a.update(&before_a, self.index_0)];
+        updates.extend(self.index_1.map(|index| b.update(&before_b, index)));
+        updates
+    }
+}
+
+impl WheelJoint {
+    pub fn indices(&self) -> Vec<usize> {
+        vec![self.index_0, self.index_1]
+    }
+
+    pub fn indices_mut(&mut self) -> Vec<&mut usize> {
+        vec![&mut self.index_0, &mut self.index_1]
+    }
+
+    // the wheel's centre and where the suspension is anchored on the chassis
+    pub fn anchors(&self, shapes: &[Shape]) -> Option<(Vector2<f32>, Vector2<f32>)> {
+        let a = Body::of(shapes, Some(self.index_0))?;
+        let b = Body::of(shapes, Some(self.index_1))?;
+        Some((a.position, b.position + b.arm(self.anchor_1)))
+    }
+
+    pub fn solve(&self, shapes: &[Shape], dt: f32) -> Vec<ConstraintUpdate> {
+        let (Some(before_a), Some(before_b)) = (
+            Body::of(shapes, Some(self.index_0)),
+            Body::of(shapes, Some(self.index_1)),
+        ) else {
+            return Vec::new();
+        };
+        let Some(local_axis) = self.axis.try_normalize(1e-6) else {
+            return Vec::new();
+        };
+        let (mut a, mut b) = (before_a, before_b);
+
+        let axis = b.arm(local_axis);
+        let normal = vector![-axis.y, axis.x];
+        // the wheel is held at its centre, by the point of the chassis under it
+        let arm_a = vector![0., 0.];
+        let arm_b = a.position - b.position;
+        let offset = a.position - (b.position + b.arm(self.anchor_1));
+        let translation = offset.dot(&axis);
+
+        let along = effective_mass(&a, arm_a, &b, arm_b, axis);
+        if along > 0. {
+            // the damping never does more than stop the suspension moving, however stiff it is
+            let speed = (a.point_velocity(arm_a) - b.point_velocity(arm_b)).dot(&axis);
+            let impulse =
+                -self.stiffness * translation * dt - (self.damping * dt).min(1. / along) * speed;
+            a.apply_impulse(impulse * axis, arm_a);
+            b.apply_impulse(-impulse * axis, arm_b);
+        }
+        if let Some(motor) = self.motor {
+            drive_rotation(&mut a, &mut b, motor.speed, motor.max_force, dt);
+        }
+        if let Some(limits) = self.limits {
+            limit_translation(&mut a, arm_a, &mut b, arm_b, axis, translation, limits);
+        }
+        lock_translation(&mut a, arm_a, &mut b, arm_b, normal, offset.dot(&normal));
+
+        vec![
+            a.update(&before_a, self.index_0),
+            b.update(&before_b, self.index_1),
+        ]
+    }
+}
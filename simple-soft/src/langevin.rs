@@ -0,0 +1,253 @@
+use std::{
+    error::Error,
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use nalgebra::{vector, Vector2};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    emitters::Distribution,
+    physics::ForceGenerator,
+    rng::SeededRng,
+    shapes::{Ball, Shape},
+    solver::EntityState,
+    state::{index_after_removal, Scene},
+};
+
+// A Langevin thermostat: the balls it applies to feel a friction force -γ m v from a surrounding fluid and
+// random kicks from its molecules, which by the fluctuation-dissipation theorem have a variance of 2 γ m T / dt
+// per component for a step of dt. Left alone the balls' velocities settle at temperature T (in joules, Boltzmann's
+// constant taken as 1) and they diffuse with a diffusion coefficient of T / (m γ).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Langevin {
+    pub temperature: f32,
+    // γ in 1/s, velocities forget where they were heading over about 1 / γ
+    pub friction: f32,
+    // only balls in one of these collision categories, every ball by default
+    #[serde(default = "all_categories")]
+    pub categories: u32,
+    #[serde(default)]
+    pub seed: u64,
+    // seeded from `seed` on the first step, cloned with the scene so rewinds and replays get the same kicks and
+    // saved with it so a scene saved mid-run carries on with them
+    #[serde(default)]
+    rng: Option<SeededRng>,
+}
+
+fn all_categories() -> u32 {
+    u32::MAX
+}
+
+impl Hash for Langevin {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.temperature.to_bits().hash(state);
+        self.friction.to_bits().hash(state);
+        self.categories.hash(state);
+        self.seed.hash(state);
+        self.rng.hash(state);
+    }
+}
+
+impl Langevin {
+    pub fn applies_to(&self, ball: &Ball) -> bool {
+        self.covers(ball.category)
+    }
+
+    // whether balls in `category` (a bitmask) are under this thermostat
+    pub fn covers(&self, category: u32) -> bool {
+        category & self.categories != 0
+    }
+
+    // T / (m γ) for a ball of `mass`, `None` without friction
+    pub fn diffusion(&self, mass: f32) -> Option<f32> {
+        (self.friction > 0.).then(|| self.temperature / (mass * self.friction))
+    }
+}
+
+// Index into `scene.langevin` of the thermostat `ball` is under. A ball is only ever under one, scenes where
+// more than one covers a ball are rejected when they are loaded, and the first one wins for balls added later.
+pub fn thermostat(scene: &Scene, ball: &Ball) -> Option<usize> {
+    scene
+        .langevin
+        .iter()
+        .position(|langevin| langevin.applies_to(ball))
+}
+
+// The friction and the kick drawn for one ball for one substep. The kick stays the same over the substep so the
+// integrator sees a smooth force.
+#[derive(Debug, Clone)]
+pub struct LangevinForceGenerator {
+    pub friction: f32,
+    pub kick: Vector2<f32>,
+    pub entity_idx: usize,
+}
+
+impl ForceGenerator for LangevinForceGenerator {
+    fn accumulate(&self, state: &EntityState, force: &Vector2<f32>) -> Vector2<f32> {
+        force - self.friction * state.mass * state.velocity + self.kick
+    }
+    fn get_entity_idx(&self) -> usize {
+        self.entity_idx
+    }
+}
+
+// Draws the kicks for every ball under a thermostat for a substep of `dt`
+pub fn forces(scene: &mut Scene, dt: f32) -> Vec<LangevinForceGenerator> {
+    let mut forces = Vec::new();
+    let thermostats: Vec<Option<usize>> = scene
+        .shapes
+        .iter()
+        .map(|shape| match shape {
+            Shape::Ball(ball) => thermostat(scene, ball),
+            Shape::Line(_) => None,
+        })
+        .collect();
+    for (k, langevin) in scene.langevin.iter_mut().enumerate() {
+        let rng = langevin
+            .rng
+            .get_or_insert_with(|| SeededRng::new(langevin.seed));
+        for (i, shape) in scene.shapes.iter().enumerate() {
+            let Shape::Ball(ball) = shape else {
+                continue;
+            };
+            if thermostats[i] != Some(k) {
+                continue;
+            }
+            let kick = Distribution::Normal {
+                mean: 0.,
+                std_dev: (2. * langevin.friction * ball.mass * langevin.temperature.max(0.) / dt)
+                    .sqrt(),
+            };
+            forces.push(LangevinForceGenerator {
+                friction: langevin.friction,
+                kick: vector![kick.sample(rng), kick.sample(rng)],
+                entity_idx: i,
+            });
+        }
+    }
+    forces
+}
+
+// Diffusion coefficient T / (m γ) that thermostat `k` gives its balls, averaged over them. `None` when it has
+// no balls or no friction.
+pub fn expected_diffusion(scene: &Scene, k: usize) -> Option<f32> {
+    let langevin = scene.langevin.get(k)?;
+    let coefficients: Vec<f32> = scene
+        .shapes
+        .iter()
+        .filter_map(|shape| match shape {
+            Shape::Ball(ball) if thermostat(scene, ball) == Some(k) => {
+                langevin.diffusion(ball.mass)
+            }
+            _ => None,
+        })
+        .collect();
+    (!coefficients.is_empty()).then(|| coefficients.iter().sum::<f32>() / coefficients.len() as f32)
+}
+
+// Mean squared displacement of the balls under each thermostat since the start of a run, written to a CSV file
+// as `t,thermostat,msd,diffusion,expected` rows, one per thermostat every step. In 2D the MSD grows as 4 D t once
+// t is well past 1 / γ, so `diffusion` (MSD / 4t) settles at the diffusion coefficient, until walls or other
+// balls get in the way, and `expected` is T / (m γ) to compare it with.
+pub struct MsdRecorder {
+    writer: BufWriter<File>,
+    // (index into `scene.shapes`, thermostat, where it started) for every ball under a thermostat
+    start: Vec<(usize, usize, Vector2<f32>)>,
+    expected: Vec<Option<f32>>,
+    // the last (MSD, diffusion coefficient) written for each thermostat
+    last: Vec<Option<(f32, f32)>>,
+}
+
+// the last values an `MsdRecorder` wrote for one thermostat
+pub struct MsdSummary {
+    pub thermostat: usize,
+    pub msd: f32,
+    pub diffusion: f32,
+    pub expected: Option<f32>,
+}
+
+impl MsdRecorder {
+    pub fn new<P: AsRef<Path>>(path: P, scene: &Scene) -> Result<Self, Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "t,thermostat,msd,diffusion,expected")?;
+        let start = scene
+            .shapes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, shape)| match shape {
+                Shape::Ball(ball) => thermostat(scene, ball).map(|k| (i, k, ball.position)),
+                _ => None,
+            })
+            .collect();
+        Ok(Self {
+            writer,
+            start,
+            expected: (0..scene.langevin.len())
+                .map(|k| expected_diffusion(scene, k))
+                .collect(),
+            last: vec![None; scene.langevin.len()],
+        })
+    }
+
+    // stops following the ball at `index`, which has been removed, and shifts the later balls down to match
+    pub fn remove_shape(&mut self, index: usize) {
+        self.start = self
+            .start
+            .iter()
+            .filter_map(|(i, k, start)| index_after_removal(*i, index).map(|i| (i, *k, *start)))
+            .collect();
+    }
+
+    // Balls that have since been removed or replaced are left out
+    pub fn record(&mut self, t: f32, scene: &Scene) -> Result<(), Box<dyn Error>> {
+        if t <= 0. {
+            return Ok(());
+        }
+        for k in 0..self.last.len() {
+            let displacements: Vec<f32> = self
+                .start
+                .iter()
+                .filter(|(_, thermostat, _)| *thermostat == k)
+                .filter_map(|(i, _, start)| match scene.shapes.get(*i) {
+                    Some(Shape::Ball(ball)) => Some((ball.position - start).magnitude_squared()),
+                    _ => None,
+                })
+                .collect();
+            if displacements.is_empty() {
+                continue;
+            }
+            let msd = displacements.iter().sum::<f32>() / displacements.len() as f32;
+            let diffusion = msd / (4. * t);
+            let expected = self.expected[k].map_or(String::new(), |value| value.to_string());
+            writeln!(
+                self.writer,
+                "{},{},{},{},{}",
+                t, k, msd, diffusion, expected
+            )?;
+            self.last[k] = Some((msd, diffusion));
+        }
+        Ok(())
+    }
+
+    // flushes the file, returning the last values written for each thermostat that had balls
+    pub fn finish(mut self) -> Result<Vec<MsdSummary>, Box<dyn Error>> {
+        self.writer.flush()?;
+        Ok(self
+            .last
+            .iter()
+            .enumerate()
+            .filter_map(|(k, last)| {
+                last.map(|(msd, diffusion)| MsdSummary {
+                    thermostat: k,
+                    msd,
+                    diffusion,
+                    expected: self.expected[k],
+                })
+            })
+            .collect())
+    }
+}
@@ -1,15 +1,8 @@
 use std::default;
 use std::f32::EPSILON;
 
-use constraints::Constraint;
-use constraints::ConstraintUpdate;
 use constraints::FixedPointConstraint;
-use constraints::SpringConstraint;
-use physics::collision_position_delta;
-use physics::elastic_collision_velocity;
-use physics::interpolate_mouse_force;
 use physics::SpringForceGenerator;
-use solver::EntityState;
 use solver::RungeKuttaIntegrator;
 extern crate generational_arena;
 extern crate nalgebra as na;
@@ -20,465 +13,732 @@ mod shapes;
 
 mod solver;
 
-use physics::wall_collision_velocity;
-use physics::Collision;
-use physics::ForceGenerator;
-use physics::ObjectForceGenerator;
-use physics::PointForceGenerator;
-use renderer::render_ball;
-use renderer::render_point_force_generator;
-use shapes::ball_ball_collision;
-use shapes::ball_line_collision;
-use shapes::ball_point_collision;
-use shapes::line_line_collision;
-use shapes::point_line_distance;
-use shapes::{Ball, Line, Shape};
+use shapes::Shape;
 
 use macroquad::input;
 use macroquad::prelude::*;
 
 mod renderer;
-use renderer::render_line;
+use renderer::{render_histogram, render_plot, DebugLayers, Renderer, ScreenRenderer};
 
 use na::{vector, Vector2};
 use physics::{calc_pos, calc_vel};
 
-use ::rand::Rng;
+use std::path::{Path, PathBuf};
 
 mod constraints;
-use constraints::DistanceConstraint;
 
-#[macroquad::main("MyGame")]
+mod joints;
 
-// fn create_balls() {
-//     Ball()
-// }
+mod vehicle;
+use vehicle::Drive;
 
-async fn main() {
-    let N = 7; // number of balls
-    let MAX_VELOCITY = 1000.;
-    let mut dt = 0.1;
-    let mut FPS = false;
-    let mut GRAVITY = false;
+mod state;
+use state::{index_after_removal, Scene, SolverSettings};
 
-    fn generate_balls(n: u32, shapes: &mut Vec<Shape>) {
-        let mut rng = ::rand::thread_rng();
-        for i in 0..n {
-            let mut ball = Ball::new_default();
-            let x: f32 = rng.gen_range(55..=900) as f32;
-            let y: f32 = rng.gen_range(55..=900) as f32;
-            let vx: f32 = rng.gen_range(-0..=0) as f32;
-            let vy: f32 = rng.gen_range(-0..=0) as f32;
-            let position = vector![x, y];
-            let velocity = vector![vx, vy];
-            ball.velocity = velocity;
-            ball.elasticity = 0.98;
-            shapes.push(Shape::Ball(ball.translate_to(position)));
-        }
-    }
+mod timeline;
+use timeline::Timeline;
 
-    let mut constraints: Vec<Constraint> = Vec::new();
+mod replay;
+use replay::{Action, Replay, StepInput};
 
-    let mut shapes: Vec<Shape> = Vec::new();
-    generate_balls(N, &mut shapes);
+mod world;
 
-    let mut collisions: Vec<(usize, Collision)> = Vec::new();
+mod sleep;
 
-    let top_wall = Line::new(vector![50., 50.], vector![1000., 50.]);
-    let left_wall = Line::new(vector![50., 1000.], vector![50., 50.]);
-    let right_wall = Line::new(vector![1000., 50.], vector![1000., 1000.]);
-    let bottom_wall = Line::new(vector![1000., 1000.], vector![50., 1000.]);
+mod export;
+use export::{ExportSettings, Recorder};
 
-    shapes.push(Shape::Line(bottom_wall));
-    shapes.push(Shape::Line(top_wall));
-    shapes.push(Shape::Line(left_wall));
-    shapes.push(Shape::Line(right_wall));
+mod diagnostics;
+use diagnostics::History;
 
-    let initial_state: Vec<Shape> = shapes.clone();
+mod events;
+use events::{ContactEvents, ContactPhase};
 
-    let mut ball_focused = false;
+mod materials;
 
-    let constraint1 = SpringConstraint {
-        index_0: 0,
-        index_1: 1,
-        distance: 50.,
-        k: 50.,
-        dampen: 0.1,
-    };
-    let constraint2 = SpringConstraint {
-        index_0: 1,
-        index_1: 2,
-        distance: 50.,
-        k: 50.,
-        dampen: 0.1,
+mod editor;
+use editor::Editor;
+
+mod sensors;
+
+mod emitters;
+
+mod fluid;
+use sensors::{SensorEvents, SensorPhase};
+
+mod gas;
+use gas::PressureGauge;
+
+mod langevin;
+use langevin::MsdRecorder;
+
+mod camera;
+use camera::{Camera, DEFAULT_PIXELS_PER_METRE};
+
+mod panel;
+use panel::{Panel, Settings};
+
+mod headless;
+use headless::{FrameFormat, FrameSettings};
+
+mod svg;
+
+mod raster;
+
+mod gif;
+
+mod rng;
+
+// command line: simple-soft [scene.ron] [--seed N] [--record replay.ron] [--replay replay.ron]
+//     [--export states.csv] [--export-interval N] [--export-entities 0,1,2] [--log-contacts] [--log-sensors]
+//     [--log-gas] [--pixels-per-metre N] [--settings settings.ron] [--solver solver.ron]
+//     [--headless STEPS | --duration SECONDS] [--svg frames/ | --png frames/ | --gif run.gif] [--msd msd.csv]
+//     [--frame-interval N] [--frame-size 800x800] [--frame-delay SECONDS]
+struct Args {
+    scene_path: String,
+    settings_path: String,
+    // solver settings to run the scene with instead of its own
+    solver_path: Option<String>,
+    seed: Option<u64>,
+    record: Option<String>,
+    replay: Option<String>,
+    export: Option<ExportSettings>,
+    log_contacts: bool,
+    log_sensors: bool,
+    log_gas: bool,
+    pixels_per_metre: Option<f32>,
+    // run this many steps, or for this many simulated seconds, without a window
+    headless: Option<u64>,
+    duration: Option<f32>,
+    frames: Option<FrameSettings>,
+    // mean squared displacement of the thermostatted balls, headless only
+    msd: Option<String>,
+}
+
+fn parse_args() -> Args {
+    let mut args = Args {
+        scene_path: String::from("scene.ron"),
+        settings_path: String::from("settings.ron"),
+        solver_path: None,
+        seed: None,
+        record: None,
+        replay: None,
+        export: None,
+        log_contacts: false,
+        log_sensors: false,
+        log_gas: false,
+        pixels_per_metre: None,
+        headless: None,
+        duration: None,
+        frames: None,
+        msd: None,
     };
-    let constraint3 = SpringConstraint {
-        index_0: 2,
-        index_1: 0,
-        distance: 50.,
-        k: 50.,
-        dampen: 0.1,
+    let mut interval = None;
+    let mut entities = None;
+    let mut frame_interval = None;
+    let mut frame_size = None;
+    let mut frame_delay = None;
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--seed" => args.seed = argv.next().and_then(|seed| seed.parse().ok()),
+            "--record" => args.record = argv.next(),
+            "--settings" => {
+                if let Some(path) = argv.next() {
+                    args.settings_path = path;
+                }
+            }
+            "--solver" => args.solver_path = argv.next(),
+            "--replay" => args.replay = argv.next(),
+            "--log-contacts" => args.log_contacts = true,
+            "--log-sensors" => args.log_sensors = true,
+            "--log-gas" => args.log_gas = true,
+            "--pixels-per-metre" => {
+                args.pixels_per_metre = argv.next().and_then(|n| n.parse().ok())
+            }
+            "--headless" => args.headless = argv.next().and_then(|n| n.parse().ok()),
+            "--duration" => args.duration = argv.next().and_then(|n| n.parse().ok()),
+            "--svg" => {
+                args.frames = argv
+                    .next()
+                    .map(|dir| FrameSettings::new(dir, FrameFormat::Svg))
+            }
+            "--png" => {
+                args.frames = argv
+                    .next()
+                    .map(|dir| FrameSettings::new(dir, FrameFormat::Png))
+            }
+            "--gif" => {
+                args.frames = argv
+                    .next()
+                    .map(|path| FrameSettings::new(path, FrameFormat::Gif))
+            }
+            "--frame-delay" => frame_delay = argv.next().and_then(|n| n.parse().ok()),
+            "--frame-interval" => frame_interval = argv.next().and_then(|n| n.parse().ok()),
+            "--frame-size" => frame_size = argv.next().and_then(|size| headless::parse_size(&size)),
+            "--msd" => args.msd = argv.next(),
+            "--export" => args.export = argv.next().map(ExportSettings::new),
+            "--export-interval" => interval = argv.next().and_then(|n| n.parse().ok()),
+            "--export-entities" => {
+                entities = argv.next().map(|list| {
+                    list.split(',')
+                        .filter_map(|index| index.trim().parse().ok())
+                        .collect()
+                })
+            }
+            _ => args.scene_path = arg,
+        }
+    }
+    if let Some(export) = &mut args.export {
+        export.interval = interval.unwrap_or(1);
+        export.entities = entities;
+    }
+    if let Some(frames) = &mut args.frames {
+        frames.interval = frame_interval.unwrap_or(1);
+        if let Some((width, height)) = frame_size {
+            frames.width = width;
+            frames.height = height;
+        }
+        frames.pixels_per_metre = args.pixels_per_metre;
+        frames.frame_delay = frame_delay;
+    }
+    args
+}
+
+// `scene_path` if it exists and loads, otherwise the default scene for `seed`. The flag is true when the
+// scene came from the file.
+fn open_scene(scene_path: &str, seed: u64) -> (Scene, bool) {
+    if !Path::new(scene_path).exists() {
+        return (Scene::new_default(seed), false);
+    }
+    match Scene::load(scene_path) {
+        Ok(scene) => (scene, true),
+        Err(err) => {
+            eprintln!("could not load scene {}: {}", scene_path, err);
+            (Scene::new_default(seed), false)
+        }
+    }
+}
+
+fn main() {
+    let args = parse_args();
+    if args.headless.is_some() || args.duration.is_some() {
+        run_headless(args);
+    } else {
+        if args.frames.is_some() {
+            eprintln!("frames are only drawn in --headless runs");
+        }
+        if args.msd.is_some() {
+            eprintln!("--msd is only written in --headless runs");
+        }
+        macroquad::Window::new("MyGame", run(args));
+    }
+}
+
+// the solver settings in `path`, if it exists and loads
+fn load_solver(path: &str) -> Option<SolverSettings> {
+    if !Path::new(path).exists() {
+        return None;
+    }
+    SolverSettings::load(path)
+        .map_err(|err| eprintln!("could not load solver settings {}: {}", path, err))
+        .ok()
+}
+
+// the scene (from the file or the seed) run with no window, for CI and batch runs
+fn run_headless(args: Args) {
+    if args.replay.is_some() || args.record.is_some() {
+        eprintln!("--record and --replay need a window, ignoring them");
+    }
+    let seed = args.seed.unwrap_or_else(::rand::random);
+    println!("seed {}", seed);
+    let (mut scene, _) = open_scene(&args.scene_path, seed);
+    if let Some(solver) = args.solver_path.as_deref().and_then(load_solver) {
+        scene.settings = solver;
+    }
+    let steps = match (args.headless, args.duration) {
+        (Some(steps), _) => steps,
+        (None, Some(duration)) => (duration / scene.settings.dt).ceil() as u64,
+        (None, None) => 0,
     };
-    constraints.push(Constraint::Spring(constraint1));
-    constraints.push(Constraint::Spring(constraint2));
-    constraints.push(Constraint::Spring(constraint3));
+    let exporter = args.export.and_then(|settings| {
+        let path = settings.path.clone();
+        Recorder::new(settings)
+            .map_err(|err| eprintln!("could not export to {}: {}", path.display(), err))
+            .ok()
+    });
+    let msd = args.msd.and_then(|path| {
+        MsdRecorder::new(&path, &scene)
+            .map_err(|err| eprintln!("could not write {}: {}", path, err))
+            .ok()
+    });
+    if let Err(err) = headless::run(scene, steps, args.frames, exporter, msd, args.log_gas) {
+        eprintln!("headless run failed: {}", err);
+        std::process::exit(1);
+    }
+}
 
-    let dist1 = DistanceConstraint::new(4, 5, 40.);
-    let dist2 = DistanceConstraint::new(5, 6, 40.);
-    let dist3 = DistanceConstraint::new(6, 4, 40.);
+// the interactive app
+async fn run(args: Args) {
+    let MAX_VELOCITY = 1000.;
+    let mut FPS = false;
+    let mut GRAVITY = false;
+
+    // scene file to open, also where S saves to and L reloads from
+    let scene_path = args.scene_path;
+
+    let replaying = args.replay.map(|path| {
+        Replay::load(&path).unwrap_or_else(|err| {
+            eprintln!("could not load replay {}: {}", path, err);
+            std::process::exit(1);
+        })
+    });
+    let seed = match &replaying {
+        Some(replay) => replay.seed,
+        None => args.seed.unwrap_or_else(::rand::random),
+    };
+    println!("seed {}", seed);
 
-    constraints.push(Constraint::Distance(dist1));
-    constraints.push(Constraint::Distance(dist2));
-    constraints.push(Constraint::Distance(dist3));
+    let (mut scene, mut from_file) = match &replaying {
+        Some(replay) => (replay.initial_scene(), false),
+        None => open_scene(&scene_path, seed),
+    };
+    // O shows the settings panel, whose state is kept in the settings file. The scene keeps its own solver
+    // settings unless `--solver` gives a file of them, which the panel's changes are then saved to.
+    let settings_path = args.settings_path;
+    let saved_settings = if Path::new(&settings_path).exists() {
+        Settings::load(&settings_path)
+            .map_err(|err| eprintln!("could not load settings {}: {}", settings_path, err))
+            .ok()
+    } else {
+        None
+    };
+    let mut panel = Panel::new(saved_settings.unwrap_or_default());
+    if let Some(solver_path) = &args.solver_path {
+        let solver = load_solver(solver_path);
+        if let (Some(solver), None) = (solver, &replaying) {
+            scene.settings = solver;
+            // a regenerated scene wouldn't have these settings, so the recording needs the scene
+            from_file = true;
+        }
+        panel.solver_file = Some((PathBuf::from(solver_path), scene.settings));
+    }
+    let mut initial_state = scene.clone();
+
+    // the recording is written out when the window is closed
+    let mut recording = args
+        .record
+        .map(|path| (path, Replay::new(seed, from_file.then(|| scene.clone()))));
+    let mut exporter = args.export.and_then(|settings| {
+        let path = settings.path.clone();
+        Recorder::new(settings)
+            .map_err(|err| eprintln!("could not export to {}: {}", path.display(), err))
+            .ok()
+    });
+    if recording.is_some() || exporter.is_some() {
+        prevent_quit();
+    }
+    // input to record with the next step
+    let mut pending = StepInput::default();
+    let mut diverged = false;
 
     use std::time::Instant;
 
     let mut t = 0.;
-    let mut integrator = RungeKuttaIntegrator::new(dt);
+    let mut step: u64 = 0;
+    let mut integrator = RungeKuttaIntegrator::new(scene.settings.dt);
+
+    // E toggles the energy and momentum plots
+    let mut show_diagnostics = false;
+    let mut history = History::new();
+
+    let mut contacts = ContactEvents::new();
+    if args.log_contacts {
+        contacts.subscribe(Box::new(|event| {
+            if event.phase != ContactPhase::Persist {
+                println!(
+                    "{:?} {} {} at ({:.1}, {:.1}) normal ({:.2}, {:.2}) depth {:.3} impulse {:.3}",
+                    event.phase,
+                    event.a,
+                    event.b,
+                    event.point.x,
+                    event.point.y,
+                    event.normal.x,
+                    event.normal.y,
+                    event.penetration,
+                    event.impulse
+                );
+            }
+        }));
+    }
+    let mut sensor_events = SensorEvents::new();
+    if args.log_sensors {
+        sensor_events.subscribe(Box::new(|event| {
+            if event.phase != SensorPhase::Stay {
+                println!(
+                    "{:?} sensor {} ball {} at ({:.1}, {:.1})",
+                    event.phase, event.sensor, event.ball, event.position.x, event.position.y
+                );
+            }
+        }));
+    }
+    // G shows the gas temperature, wall pressures and speed histogram
+    let mut show_gas = false;
+    let mut gauge = PressureGauge::new(1.);
+    // F1-F5 toggle the velocity, force, contact, bounding box and strain overlays
+    let mut debug = DebugLayers::default();
+    let mut last_collisions = Vec::new();
+    // where balls hit something last step, drawn as a flash
+    let mut impacts: Vec<Vector2<f32>> = Vec::new();
+    history.push(&diagnostics::measure(&scene));
+
+    // Tab switches between simulating and editing the scene
+    let mut editor = Editor::new();
+
+    // the scene is in metres, the right mouse button pans the view and the wheel zooms it
+    let mut camera = Camera::looking_at(
+        &scene,
+        args.pixels_per_metre.unwrap_or(DEFAULT_PIXELS_PER_METRE),
+        vector![screen_width(), screen_height()],
+    );
+    let mut pan_from: Option<Vector2<f32>> = None;
+
+    let mut paused = false;
+    let mut timeline = Timeline::new();
+    timeline.record(step, t, &scene);
     loop {
-        let mut forces: Vec<Box<dyn ForceGenerator>> = Vec::new();
         let now: Instant = Instant::now();
         clear_background(RED);
 
         let mpos = input::mouse_position();
-        let mpoint = vector![mpos.0, mpos.1];
+        let mscreen = vector![mpos.0, mpos.1];
+        camera.viewport = vector![screen_width(), screen_height()];
+        let over_panel = panel.contains(&scene, editor.selected, mscreen);
+        let mut panel_actions = panel.update(&scene, editor.selected);
+        if replaying.is_some() {
+            // the recording already has what happens to the scene
+            panel_actions.clear();
+        }
+        if is_mouse_button_down(MouseButton::Right) {
+            if let Some(from) = pan_from {
+                camera.pan(mscreen - from);
+            }
+            pan_from = Some(mscreen);
+        } else {
+            pan_from = None;
+        }
+        let wheel = mouse_wheel().1;
+        if wheel != 0. && !over_panel {
+            camera.zoom(1.1_f32.powf(wheel.signum()), mscreen);
+        }
+        // cursor in world coordinates
+        let mpoint = camera.screen_to_world(mscreen);
 
-        if input::is_key_down(KeyCode::R) {
-            // reset
-            shapes = initial_state.clone();
+        if input::is_key_pressed(KeyCode::P) {
+            // resuming from a past frame drops the frames after it on the next record
+            paused = !paused;
         }
-        if input::is_key_pressed(KeyCode::M) {
-            integrator.increase_dt();
+        if input::is_key_pressed(KeyCode::Tab) && replaying.is_none() {
+            if !editor.active {
+                editor.enter();
+            } else {
+                editor.leave();
+                if editor.edited {
+                    // the edited scene is a new starting point, like loading one
+                    scene.mouse_joint = None;
+                    initial_state = scene.clone();
+                    integrator.set_dt(scene.settings.dt);
+                    t = 0.;
+                    step = 0;
+                    timeline.clear();
+                    timeline.record(step, t, &scene);
+                    history.clear();
+                    contacts.clear();
+                    gauge.clear();
+                    sensor_events.clear();
+                    if let Some((_, replay)) = &mut recording {
+                        *replay = Replay::new(seed, Some(scene.clone()));
+                    }
+                    pending = StepInput::default();
+                }
+            }
+        }
+        if editor.active && !over_panel {
+            editor.update(&mut scene, &camera);
         }
-        if input::is_key_pressed(KeyCode::N) {
-            integrator.decrease_dt();
+        if paused && !editor.active {
+            // scrub through the timeline, holding the key keeps stepping
+            let frame = if input::is_key_down(KeyCode::Left) {
+                timeline.step_back()
+            } else if input::is_key_down(KeyCode::Right) {
+                timeline.step_forward()
+            } else {
+                None
+            };
+            if let Some(frame) = frame {
+                scene = frame.scene.clone();
+                t = frame.t;
+                step = frame.step;
+                integrator.set_dt(scene.settings.dt);
+                // anything pressed since the last step was applied to the frame we just left
+                pending = StepInput::default();
+                contacts.clear();
+                gauge.clear();
+                sensor_events.clear();
+            }
+        }
+        if input::is_key_pressed(KeyCode::S) {
+            match scene.save(&scene_path) {
+                Ok(()) => println!("saved scene to {}", scene_path),
+                Err(err) => eprintln!("could not save scene {}: {}", scene_path, err),
+            }
+        }
+        if input::is_key_pressed(KeyCode::L) && replaying.is_none() && !editor.active {
+            match Scene::load(&scene_path) {
+                Ok(loaded) => {
+                    scene = loaded;
+                    if let Some((_, solver)) = &panel.solver_file {
+                        scene.settings = *solver;
+                    }
+                    initial_state = scene.clone();
+                    integrator.set_dt(scene.settings.dt);
+                    t = 0.;
+                    step = 0;
+                    timeline.clear();
+                    timeline.record(step, t, &scene);
+                    history.clear();
+                    contacts.clear();
+                    gauge.clear();
+                    sensor_events.clear();
+                    editor.selected = None;
+                    // a loaded scene starts a new recording
+                    if let Some((_, replay)) = &mut recording {
+                        *replay = Replay::new(seed, Some(scene.clone()));
+                    }
+                    pending = StepInput::default();
+                }
+                Err(err) => eprintln!("could not load scene {}: {}", scene_path, err),
+            }
         }
         if input::is_key_pressed(KeyCode::F) {
             FPS = !FPS;
         }
+        if input::is_key_pressed(KeyCode::E) {
+            show_diagnostics = !show_diagnostics;
+        }
+        if input::is_key_pressed(KeyCode::G) {
+            show_gas = !show_gas;
+        }
+        for (key, layer) in [
+            (KeyCode::F1, &mut debug.velocities),
+            (KeyCode::F2, &mut debug.forces),
+            (KeyCode::F3, &mut debug.contacts),
+            (KeyCode::F4, &mut debug.aabbs),
+            (KeyCode::F5, &mut debug.strain),
+        ] {
+            if input::is_key_pressed(key) {
+                *layer = !*layer;
+            }
+        }
         if input::is_key_pressed(KeyCode::Space) {
             GRAVITY = !GRAVITY;
         }
 
-        for (i, shape) in shapes.iter_mut().enumerate() {
-            match shape {
-                Shape::Ball(ball) => {
-                    // let mut mouse_point_force_generator =
-                    //     PointForceGenerator::new(10., vector![0., 0.], i);
-                    // mouse_point_force_generator.position = mpoint;
-                    // forces.push(Box::new(mouse_point_force_generator));
-
-                    let gravity = ObjectForceGenerator::new(9.8, vector![0., 1.], i);
-                    forces.push(Box::new(gravity));
-                    if is_mouse_button_down(MouseButton::Left) {
-                        if ball.clicked {
-                            let force = interpolate_mouse_force(
-                                ball.position,
-                                mpoint,
-                                ball.velocity,
-                                30. / integrator.dt(),
-                                0.9,
-                            );
-                            println!("{}", force);
-                            ball.velocity = force;
-                            // let drag: ObjectForceGenerator =
-                            // ObjectForceGenerator::new(0., force.normalize(), i);
-                            // forces.push(Box::new(drag));
-                        } else if !ball_focused && ball_point_collision(&ball, &mpoint, 20.0) {
-                            ball.clicked = true;
-                            ball_focused = true;
-                            ball.position = mpoint;
-                            ball.color = BLACK;
-                        }
-                    } else {
-                        ball.clicked = false;
-                        ball_focused = false;
-                        ball.color = WHITE;
-                    }
-
-                    render_ball(ball);
+        // Inputs that change the simulation are applied through `StepInput` so that they can be recorded
+        // and replayed. While replaying, the recorded input for the step about to be taken is used instead.
+        let step_input = match &replaying {
+            _ if editor.active => {
+                // panel changes are edits like any other, with one undo step per slider drag
+                if panel.first_edit() {
+                    editor.push_undo(&scene);
+                }
+                StepInput {
+                    mouse: None,
+                    actions: panel_actions,
+                    drive: None,
                 }
-                Shape::Line(line) => render_line(&line),
             }
-        }
-
-        for (i, obj1) in shapes.iter().enumerate() {
-            for (j, obj2) in shapes[i + 1..].iter().enumerate() {
-                let shapes_j_index = i + j + 1;
-                // collision logic
-                match (obj1, obj2) {
-                    (Shape::Ball(ball1), Shape::Ball(ball2)) => {
-                        // ball 1 is i; ball2 is shapes_j_index
-                        if ball_ball_collision(ball1, ball2) {
-                            // NOTE: it might be better to calculate everything here and have Collision store only the final values
-                            // Then in application loop, all that happens is that ball is translated by pos stored in Collision
-                            // And ball velocity is set to whatever is stored in Collision
-                            // It would simplify Collision
-                            let m_a = ball1.mass;
-                            let m_b = ball2.mass;
-
-                            let vi_a = ball1.velocity;
-                            let vi_b = ball2.velocity;
-
-                            let c_r = ball1.elasticity.min(ball2.elasticity); // just use the lesser elasticity value kind of a hack
-
-                            // used for normal and for position transform
-                            let d = ball2.position - ball1.position;
-                            let distance = d.magnitude();
-                            if distance < 1e-6 {
-                                return;
-                            } // Avoid division by zero or very small distances
-                            let total_radius = ball1.radius + ball2.radius;
-                            let collision_depth = total_radius - distance;
-                            let normal = d / distance;
-
-                            let mass_sum = m_a + m_b;
-                            let translate_by = collision_position_delta(normal, collision_depth);
-
-                            // Tranlsations are prop. to the masses of the balls - i.e when equal, is equivalnet to just dividing translation equally.
-                            let translate_by_a = (translate_by * (m_b / mass_sum));
-                            let translate_by_b = (translate_by * (m_a / mass_sum));
-
-                            let (vf_a, vf_b) = elastic_collision_velocity(ball1, ball2);
-
-                            let collision_1 = Collision::new(-translate_by_a, c_r * vf_a);
-                            let collision_2 = Collision::new(translate_by_b, c_r * vf_b);
-
-                            collisions.push((i, collision_1));
-                            collisions.push((shapes_j_index, collision_2));
-                        }
-                    }
-                    (Shape::Ball(ball), Shape::Line(line)) => {
-                        if ball_line_collision(ball, line) {
-                            // println!("Collision detected between ball and line!");
-                            let mut normal = line.normal();
-                            let ball_to_line = line.start_point - ball.position;
-                            let c_r = ball.elasticity.min(line.elasticity);
-                            let friction = ball.friction.min(line.friction);
-
-                            if ball_to_line.dot(&normal) < 0.0 {
-                                // If the normal is facing the wrong way, flip it
-                                normal = -normal;
-                            }
-
-                            // Calculate the collision depth
-                            let collision_depth =
-                                ball.radius - point_line_distance(&line, &ball.position);
-
-                            // If collision depth is positive, calculate the translation vector to separate them
-                            if collision_depth > 0. {
-                                let translate_by_ball =
-                                    -collision_position_delta(normal, collision_depth);
-
-                                // Compute the final velocity after collision
-                                let vf_ball =
-                                    wall_collision_velocity(normal, c_r, friction, dt, ball);
-
-                                // Create a collision object to store translation and velocity updates for the ball
-                                let collision = Collision::new(translate_by_ball, vf_ball);
-
-                                // Add collision object for the ball
-                                collisions.push((i, collision));
-                            }
-                        }
-                    }
-                    (Shape::Line(line), Shape::Ball(ball)) => {
-                        if ball_line_collision(ball, line) {
-                            // Calculate collision normal (line's normal direction)
-                            let mut normal = line.normal();
-                            let ball_to_line = line.start_point - ball.position;
-                            let c_r = ball.elasticity.min(line.elasticity);
-                            let friction = ball.friction.min(line.friction);
-
-                            if ball_to_line.dot(&normal) > 0.0 {
-                                // If the normal is facing the wrong way, flip it
-                                normal = -normal;
-                            }
-                            // Calculate the collision depth
-                            let collision_depth =
-                                ball.radius - point_line_distance(&line, &ball.position);
-
-                            // If collision depth is positive, calculate the translation vector to separate them
-                            if collision_depth > 0. {
-                                let translate_by_ball =
-                                    collision_position_delta(normal, collision_depth);
-
-                                // Compute the final velocity after collision
-                                let vf_ball =
-                                    wall_collision_velocity(normal, c_r, friction, dt, ball);
-
-                                // Create a collision object to store translation and velocity updates for the ball
-                                let collision = Collision::new(translate_by_ball, vf_ball);
-
-                                // Add collision object for the ball
-                                collisions.push((shapes_j_index, collision)); // Note the different index here
-                            }
-                        }
+            Some(replay) if !paused => replay.input(step),
+            Some(_) => StepInput::default(),
+            None => {
+                let mut live = StepInput::default();
+                // a ball being dragged can be dragged over the panel
+                if is_mouse_button_down(MouseButton::Left)
+                    && (!over_panel || scene.mouse_joint.is_some())
+                {
+                    live.mouse = Some(mpoint);
+                }
+                if input::is_key_down(KeyCode::R) {
+                    live.actions.push(Action::Reset);
+                }
+                if input::is_key_pressed(KeyCode::M) {
+                    live.actions.push(Action::IncreaseDt);
+                }
+                if input::is_key_pressed(KeyCode::N) {
+                    live.actions.push(Action::DecreaseDt);
+                }
+                // Right drives vehicles ahead, Left reverses and Down brakes
+                let throttle = match (
+                    input::is_key_down(KeyCode::Right),
+                    input::is_key_down(KeyCode::Left),
+                ) {
+                    (true, false) => 1.,
+                    (false, true) => -1.,
+                    _ => 0.,
+                };
+                let brake = input::is_key_down(KeyCode::Down);
+                if throttle != 0. || brake {
+                    live.drive = Some(Drive { throttle, brake });
+                }
+                live.actions.extend(panel_actions);
+                live
+            }
+        };
+
+        for action in &step_input.actions {
+            match action {
+                Action::Reset => {
+                    scene = initial_state.clone();
+                    t = 0.;
+                    timeline.clear();
+                    timeline.record(step, t, &scene);
+                    history.clear();
+                    contacts.clear();
+                    gauge.clear();
+                    sensor_events.clear();
+                }
+                Action::IncreaseDt => {
+                    integrator.increase_dt();
+                    scene.settings.dt = integrator.dt();
+                }
+                Action::DecreaseDt => {
+                    integrator.decrease_dt();
+                    scene.settings.dt = integrator.dt();
+                }
+                Action::Settings(settings) => {
+                    scene.settings = *settings;
+                    integrator.set_dt(settings.dt);
+                }
+                Action::Body {
+                    index,
+                    mass,
+                    radius,
+                    elasticity,
+                    friction,
+                } => match scene.shapes.get_mut(*index) {
+                    Some(Shape::Ball(ball)) => {
+                        ball.set_mass(*mass);
+                        ball.radius = *radius;
+                        ball.elasticity = *elasticity;
+                        ball.friction = *friction;
                     }
-                    (Shape::Line(line1), Shape::Line(line2)) => {
-                        if line_line_collision(line1, line2) {
-                            // Handle collision
-                        }
+                    Some(Shape::Line(line)) => {
+                        line.elasticity = *elasticity;
+                        line.friction = *friction;
                     }
-                    _ => {}
-                }
+                    None => {}
+                },
+            }
+        }
+        pending.actions.extend(step_input.actions.iter().copied());
+
+        let running = !paused && !editor.active;
+        if running {
+            // dragging would change a frame that is already in the timeline
+            world::drag(&mut scene, step_input.mouse);
+            for vehicle in &mut scene.vehicles {
+                vehicle.input = step_input.drive;
+            }
+            // the panel shows the ball that was grabbed last
+            if let Some(joint) = &scene.mouse_joint {
+                editor.selected = Some(joint.entity_idx);
             }
         }
 
-        for (i, collision) in &collisions {
-            match (&mut shapes[*i]) {
-                (Shape::Ball(ball)) => {
-                    // ball.translate_by(10. * unit);
-                    // if &ball.velocity.dot(&unit) < &0. {
-                    //     ball.velocity = ball.velocity - 2. * *value;
-                    // }
-
-                    // TODO: add properties to each object for elasticity and friction coeff. so that this can be updated, and add these to Collision
-                    // TODO: change these function names to not be wall but just general collision since its not only wall
-                    let pos_delta = collision.translate_by;
-
-                    // TODO: remove elasticity from collision, should just be property of lines and balls
-                    ball.translate_by(pos_delta);
+        let mut screen = ScreenRenderer::new(&camera);
+        screen.scene(&scene, &sensor_events);
 
-                    let ball_vel = collision.vf;
+        if let Some(joint) = &scene.mouse_joint {
+            if let Shape::Ball(ball) = &scene.shapes[joint.entity_idx] {
+                let anchor = camera.world_to_screen(ball.position + joint.anchor);
+                let target = camera.world_to_screen(joint.target);
+                screen.segment(anchor, target, 1., YELLOW);
+            }
+        }
 
-                    ball.velocity = ball_vel;
-                }
+        for point in &impacts {
+            screen.circle_outline(camera.world_to_screen(*point), 6., 2., YELLOW);
+        }
 
-                (Shape::Line(line)) => {
-                    // println!("match line");
-                }
-                _ => {}
-            }
+        if debug.any() {
+            screen.debug(&debug, &scene, &last_collisions);
         }
-        collisions.clear();
-
-        let mut updates: Vec<ConstraintUpdate> = Vec::new();
-        for constraint in &constraints {
-            match constraint {
-                (Constraint::Spring(constraint)) => {
-                    if let (Shape::Ball(ball1), Shape::Ball(ball2)) =
-                        (&shapes[constraint.index_0], &shapes[constraint.index_1])
-                    {
-                        // Calculate the delta between ball positions
-                        let p0 = ball1.position;
-                        let p1 = ball2.position;
-                        let v0 = ball1.velocity;
-                        let v1 = ball2.velocity;
-
-                        let delta = p1 - p0;
-                        let distance = delta.magnitude();
-                        let direction = delta / distance;
-
-                        let required_delta = direction * constraint.distance;
-                        let force = constraint.k * (required_delta - delta);
-
-                        let mut update_1 = ConstraintUpdate::default();
-                        let mut update_2 = ConstraintUpdate::default();
-
-                        // let force = ObjectForceGenerator {
-                        //     strength: force.magnitude(),
-                        //     direction: force.normalize(),
-                        //     entity_idx: constraint.index_0,
-                        // };
-
-                        // let force2 = ObjectForceGenerator {
-                        //     strength: force.magnitude(),
-                        //     direction: force.normalize(),
-                        //     entity_idx: constraint.index_0,
-                        // };
-
-                        // Update velocities due to spring force
-                        update_1.velocity_update = -force * dt / ball1.mass;
-                        update_2.velocity_update = force * dt / ball2.mass;
-
-                        // Calculate relative velocity along the direction of the spring
-                        let vrel = (v1 - v0).dot(&direction);
-
-                        // Apply damping factor
-                        let damping_factor = (-constraint.k * dt).exp();
-                        let new_vrel = vrel * damping_factor;
-                        let vrel_delta = new_vrel - vrel;
-                        let vrel_delta_vec = vrel_delta * direction;
-
-                        // Update velocities due to damping
-                        update_1.velocity_update += -vrel_delta_vec / 2.0;
-                        update_2.velocity_update += vrel_delta_vec / 2.0;
-
-                        update_1.index = constraint.index_0;
-                        update_2.index = constraint.index_1;
-
-                        // let force = SpringForceGenerator {
-                        //     b: constraint.dampen,
-                        //     k: constraint.k,
-                        //     length: constraint.distance,
-                        //     displacement: delta / 2.,
-                        //     entity_idx: constraint.index_0,
-                        // };
-
-                        // let force2 = SpringForceGenerator {
-                        //     b: constraint.dampen,
-                        //     k: constraint.k,
-                        //     length: constraint.distance,
-                        //     displacement: -delta / 2.,
-                        //     entity_idx: constraint.index_1,
-                        // };
-
-                        // forces.push(Box::new(force));
-                        // forces.push(Box::new(force2));
-                        updates.push(update_1);
-                        updates.push(update_2);
-                        // Optionally: Draw the spring
-                        render_line(&Line::new(ball1.position, ball2.position));
-                    }
+        if editor.active {
+            editor.draw(&scene, &camera);
+        }
+        panel.draw(&scene, editor.selected);
+        panel.save_if_changed(&scene.settings, &settings_path);
+
+        if running {
+            let output = world::step(&mut scene, &integrator, t);
+            // shapes removed by emitters shift the indices of everything after them
+            for &index in &output.removed {
+                contacts.remove_shape(index);
+                sensor_events.remove_shape(&scene, index);
+                gauge.remove_shape(index);
+                if let Some(recorder) = &mut exporter {
+                    recorder.remove_shape(index);
                 }
-                (Constraint::Distance(constraint)) => {
-                    if let (Shape::Ball(ball1), Shape::Ball(ball2)) =
-                        (&shapes[constraint.index_0], &shapes[constraint.index_1])
-                    {
-                        let delta = ball2.position - ball1.position;
-                        let total_correction = delta.magnitude() - constraint.distance;
-                        let norm = delta.normalize();
-                        let offset = norm * total_correction;
-
-                        let mut update_1 = ConstraintUpdate::default();
-                        let mut update_2 = ConstraintUpdate::default();
-
-                        update_1.position_update = offset / 2.;
-                        update_2.position_update = -offset / 2.;
-
-                        update_1.index = constraint.index_0;
-                        update_2.index = constraint.index_1;
-
-                        updates.push(update_1);
-                        updates.push(update_2);
-                    }
+                editor.selected = editor
+                    .selected
+                    .and_then(|selected| index_after_removal(selected, index));
+            }
+            let collisions = output.collisions;
+            contacts.update(&scene, &collisions);
+            impacts = contacts
+                .drain()
+                .filter(|event| event.phase == ContactPhase::Begin)
+                .map(|event| event.point)
+                .collect();
+            sensor_events.update(&scene);
+            sensor_events.drain();
+            gauge.update(&scene, &collisions, integrator.dt());
+            if args.log_gas {
+                gas::log_if_second_elapsed(t, integrator.dt(), &scene, &gauge);
+            }
+            last_collisions = collisions;
+            t += integrator.dt();
+            let hash = scene.state_hash();
+
+            if let Some(replay) = &replaying {
+                if step < replay.len() && !replay.check(step, hash) && !diverged {
+                    eprintln!("replay diverged from the recording at step {}", step);
+                    diverged = true;
                 }
-                (Constraint::FixedPoint(constraint)) => {
-                    if let (Shape::Ball(ball)) = &shapes[constraint.index] {
-                        let delta = ball.position - constraint.position;
-                        let mut update = ConstraintUpdate::default();
-                        update.position_update = -delta;
-                        update.index = constraint.index;
-                        updates.push(update);
-                    }
+                if step + 1 == replay.len() {
+                    println!("replay finished after {} steps", replay.len());
+                    paused = true;
                 }
             }
-        }
+            if let Some((_, replay)) = &mut recording {
+                pending.mouse = step_input.mouse;
+                pending.drive = step_input.drive;
+                replay.record(step, &pending, hash);
+            }
+            pending = StepInput::default();
+
+            step += 1;
+            timeline.record(step, t, &scene);
+            history.push(&diagnostics::measure(&scene));
 
-        for update in &updates {
-            let shape = &mut shapes[update.index];
-            match shape {
-                Shape::Ball(ball) => {
-                    ball.position += update.position_update;
-                    ball.color = BLUE;
-                    ball.velocity += update.velocity_update;
-                    // ball.force += update.force_update;
+            if let Some(recorder) = &mut exporter {
+                if let Err(err) = recorder.record(step, t, &scene) {
+                    eprintln!("stopped exporting: {}", err);
+                    exporter = None;
                 }
-                _ => {}
             }
         }
-        updates.clear();
 
         draw_text(
             format!("{}", integrator.dt()).as_str(),
@@ -500,29 +760,83 @@ async fn main() {
             );
         }
 
-        for force in &forces {
-            let idx = force.get_entity_idx();
-            // TODO: have trait of shapes be that they have EntityState, then for line make start pos and end pos a fn and have line be defined
-            // normally with 'x' in entity state (pos), and then a norm vector and a length, so x is start point, norm is direction of line
-            // for v just always 0 uness implement v for lines
-
-            // TODO: set velocity to 0 if very small
-            if let Shape::Ball(ball) = &mut shapes[idx] {
-                let state = EntityState {
-                    velocity: ball.velocity,
-                    position: ball.position,
-                    mass: ball.mass,
-                };
-
-                let (x_update, v_update) = integrator.integrate(&state, &forces, t);
-                // println!("x update {:?}", x_update);
-                // println!("v update {:?}", v_update);
+        if paused {
+            draw_text(
+                format!("PAUSED {}/{}", timeline.cursor() + 1, timeline.len()).as_str(),
+                100.,
+                60.0,
+                20.0,
+                WHITE,
+            );
+        }
+        if show_diagnostics {
+            let width = screen_width().min(600.);
+            let x = screen_width() - width - 10.;
+            render_plot("energy", &history.energy, x, 10., width, 160.);
+            render_plot("momentum", &history.momentum, x, 180., width, 160.);
+        }
+        if show_gas {
+            for (index, pressure) in gauge.pressures(&scene) {
+                if let Shape::Line(line) = &scene.shapes[index] {
+                    let middle = camera.world_to_screen(0.5 * (line.start_point + line.end_point));
+                    draw_text(
+                        format!("{:.2}", pressure).as_str(),
+                        middle.x + 4.,
+                        middle.y - 4.,
+                        16.,
+                        WHITE,
+                    );
+                }
+            }
+            if let Some(temperature) = gas::temperature(&scene) {
+                // up to where the distribution has all but died out, or the fastest ball
+                let mass = scene
+                    .shapes
+                    .iter()
+                    .filter_map(|shape| match shape {
+                        Shape::Ball(ball) => Some(ball.mass),
+                        _ => None,
+                    })
+                    .fold(f32::INFINITY, f32::min);
+                let max_speed = (4. * (temperature / mass).sqrt()).max(0.01);
+                let histogram = gas::speed_histogram(&scene, temperature, 20, max_speed);
+                let width = screen_width().min(400.);
+                let y = if show_diagnostics { 350. } else { 10. };
+                render_histogram(
+                    gas::summary(&scene, &gauge).as_str(),
+                    &histogram,
+                    max_speed,
+                    screen_width() - width - 10.,
+                    y,
+                    width,
+                    160.,
+                );
+            }
+        }
+        if replaying.is_some() {
+            draw_text(
+                format!("REPLAY step {}", step).as_str(),
+                100.,
+                80.0,
+                20.0,
+                WHITE,
+            );
+        }
 
-                ball.velocity += v_update;
-                ball.position += x_update;
-            };
+        if is_quit_requested() {
+            if let Some((path, replay)) = &recording {
+                match replay.save(path) {
+                    Ok(()) => println!("saved replay of {} steps to {}", replay.len(), path),
+                    Err(err) => eprintln!("could not save replay {}: {}", path, err),
+                }
+            }
+            if let Some(recorder) = &mut exporter {
+                if let Err(err) = recorder.finish() {
+                    eprintln!("could not finish export: {}", err);
+                }
+            }
+            break;
         }
-        t += dt;
         next_frame().await;
     }
 }
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::shapes::Shape;
+
+// How the values of two touching shapes are combined into the one used for the contact
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum CombineMode {
+    #[default]
+    Min,
+    Max,
+    Average,
+    Multiply,
+    GeometricMean,
+}
+
+impl CombineMode {
+    pub fn combine(&self, a: f32, b: f32) -> f32 {
+        match self {
+            CombineMode::Min => a.min(b),
+            CombineMode::Max => a.max(b),
+            CombineMode::Average => (a + b) / 2.,
+            CombineMode::Multiply => a * b,
+            CombineMode::GeometricMean => (a * b).sqrt(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Material {
+    // coefficient of restitution
+    pub elasticity: f32,
+    // kinetic and static Coulomb friction coefficients
+    pub friction: f32,
+    pub static_friction: f32,
+}
+
+// Values used for one particular pair of materials instead of combining them, missing ones are still combined
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairOverride {
+    pub a: String,
+    pub b: String,
+    #[serde(default)]
+    pub elasticity: Option<f32>,
+    #[serde(default)]
+    pub friction: Option<f32>,
+    #[serde(default)]
+    pub static_friction: Option<f32>,
+}
+
+// Named materials that shapes can refer to with `material: Some("ice")`. Shapes without a material use
+// their own `elasticity` and `friction`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Materials {
+    pub table: BTreeMap<String, Material>,
+    pub elasticity_combine: CombineMode,
+    pub friction_combine: CombineMode,
+    pub overrides: Vec<PairOverride>,
+}
+
+impl Default for Materials {
+    fn default() -> Self {
+        // (name, elasticity, kinetic friction, static friction)
+        let table = [
+            ("rubber", 0.9, 0.8, 1.0),
+            ("ice", 0.1, 0.03, 0.1),
+            ("steel", 0.6, 0.5, 0.7),
+            ("wood", 0.4, 0.3, 0.5),
+        ]
+        .into_iter()
+        .map(|(name, elasticity, friction, static_friction)| {
+            (
+                name.to_string(),
+                Material {
+                    elasticity,
+                    friction,
+                    static_friction,
+                },
+            )
+        })
+        .collect();
+        Self {
+            table,
+            // min matches what collisions did before there were materials
+            elasticity_combine: CombineMode::Min,
+            friction_combine: CombineMode::Min,
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl Materials {
+    pub fn get(&self, name: &str) -> Option<&Material> {
+        self.table.get(name)
+    }
+
+    // the shape's material, or its own values when it has none (or an unknown one)
+    pub fn surface(&self, shape: &Shape) -> Material {
+        let (material, elasticity, friction, static_friction) = match shape {
+            Shape::Ball(ball) => (
+                &ball.material,
+                ball.elasticity,
+                ball.friction,
+                ball.static_friction,
+            ),
+            Shape::Line(line) => (
+                &line.material,
+                line.elasticity,
+                line.friction,
+                line.static_friction,
+            ),
+        };
+        material
+            .as_deref()
+            .and_then(|name| self.get(name))
+            .copied()
+            .unwrap_or(Material {
+                elasticity,
+                friction,
+                static_friction: static_friction.unwrap_or(friction),
+            })
+    }
+
+    // elasticity and friction for a contact between `a` and `b`
+    pub fn pair(&self, a: &Shape, b: &Shape) -> Material {
+        let (surface_a, surface_b) = (self.surface(a), self.surface(b));
+        let mut combined = Material {
+            elasticity: self
+                .elasticity_combine
+                .combine(surface_a.elasticity, surface_b.elasticity),
+            friction: self
+                .friction_combine
+                .combine(surface_a.friction, surface_b.friction),
+            static_friction: self
+                .friction_combine
+                .combine(surface_a.static_friction, surface_b.static_friction),
+        };
+        if let (Some(name_a), Some(name_b)) = (material_name(a), material_name(b)) {
+            let found = self
+                .overrides
+                .iter()
+                .find(|o| (o.a == name_a && o.b == name_b) || (o.a == name_b && o.b == name_a));
+            if let Some(pair_override) = found {
+                combined.elasticity = pair_override.elasticity.unwrap_or(combined.elasticity);
+                combined.friction = pair_override.friction.unwrap_or(combined.friction);
+                combined.static_friction = pair_override
+                    .static_friction
+                    .unwrap_or(combined.static_friction);
+            }
+        }
+        combined
+    }
+}
+
+fn material_name(shape: &Shape) -> Option<&str> {
+    match shape {
+        Shape::Ball(ball) => ball.material.as_deref(),
+        Shape::Line(line) => line.material.as_deref(),
+    }
+}
@@ -0,0 +1,420 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use macroquad::prelude::*;
+use nalgebra::{vector, Vector2};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    replay::Action,
+    shapes::Shape,
+    solver::TimeIntegrator,
+    state::{Scene, SolverSettings},
+};
+
+const WIDTH: f32 = 340.;
+const ROW_HEIGHT: f32 = 22.;
+const LABEL_WIDTH: f32 = 100.;
+const VALUE_WIDTH: f32 = 90.;
+const MARGIN: f32 = 10.;
+
+// What the panel keeps between runs, stored as RON like scenes. Only the panel's own state is kept here, the
+// solver settings belong to the scene.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub open: bool,
+}
+
+impl Settings {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        Ok(ron::from_str(&text)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    GravityX,
+    GravityY,
+    Integrator,
+    Dt,
+    Substeps,
+    Iterations,
+    Damping,
+    Mass,
+    Radius,
+    Elasticity,
+    Friction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scale {
+    Linear,
+    // for values that span orders of magnitude
+    Log,
+    Integer,
+}
+
+impl Field {
+    const SOLVER: [Field; 7] = [
+        Field::GravityX,
+        Field::GravityY,
+        Field::Integrator,
+        Field::Dt,
+        Field::Substeps,
+        Field::Iterations,
+        Field::Damping,
+    ];
+    const BALL: [Field; 4] = [
+        Field::Mass,
+        Field::Radius,
+        Field::Elasticity,
+        Field::Friction,
+    ];
+    const LINE: [Field; 2] = [Field::Elasticity, Field::Friction];
+
+    fn label(self) -> &'static str {
+        match self {
+            Field::GravityX => "gravity x",
+            Field::GravityY => "gravity y",
+            Field::Integrator => "integrator",
+            Field::Dt => "dt",
+            Field::Substeps => "substeps",
+            Field::Iterations => "iterations",
+            Field::Damping => "damping",
+            Field::Mass => "mass",
+            Field::Radius => "radius",
+            Field::Elasticity => "elasticity",
+            Field::Friction => "friction",
+        }
+    }
+
+    fn range(self) -> (f32, f32, Scale) {
+        match self {
+            Field::GravityX | Field::GravityY => (-20., 20., Scale::Linear),
+            Field::Integrator => (0., (TimeIntegrator::ALL.len() - 1) as f32, Scale::Integer),
+            Field::Dt => (0.001, 0.1, Scale::Log),
+            Field::Substeps => (1., 16., Scale::Integer),
+            Field::Iterations => (1., 16., Scale::Integer),
+            Field::Damping => (0., 2., Scale::Linear),
+            Field::Mass => (0.1, 100., Scale::Log),
+            Field::Radius => (0.05, 2., Scale::Log),
+            Field::Elasticity => (0., 1., Scale::Linear),
+            Field::Friction => (0., 2., Scale::Linear),
+        }
+    }
+
+    fn value(self, solver: &SolverSettings, shape: Option<&Shape>) -> f32 {
+        match (self, shape) {
+            (Field::GravityX, _) => solver.gravity.x,
+            (Field::GravityY, _) => solver.gravity.y,
+            (Field::Integrator, _) => TimeIntegrator::ALL
+                .iter()
+                .position(|integrator| *integrator == solver.integrator)
+                .unwrap_or(0) as f32,
+            (Field::Dt, _) => solver.dt,
+            (Field::Substeps, _) => solver.substeps as f32,
+            (Field::Iterations, _) => solver.iterations as f32,
+            (Field::Damping, _) => solver.damping,
+            (Field::Mass, Some(Shape::Ball(ball))) => ball.mass,
+            (Field::Radius, Some(Shape::Ball(ball))) => ball.radius,
+            (Field::Elasticity, Some(Shape::Ball(ball))) => ball.elasticity,
+            (Field::Elasticity, Some(Shape::Line(line))) => line.elasticity,
+            (Field::Friction, Some(Shape::Ball(ball))) => ball.friction,
+            (Field::Friction, Some(Shape::Line(line))) => line.friction,
+            _ => 0.,
+        }
+    }
+
+    fn set(self, value: f32, solver: &mut SolverSettings, shape: Option<&mut Shape>) {
+        match (self, shape) {
+            (Field::GravityX, _) => solver.gravity.x = value,
+            (Field::GravityY, _) => solver.gravity.y = value,
+            (Field::Integrator, _) => solver.integrator = TimeIntegrator::ALL[value as usize],
+            (Field::Dt, _) => solver.dt = value,
+            (Field::Substeps, _) => solver.substeps = value as u32,
+            (Field::Iterations, _) => solver.iterations = value as u32,
+            (Field::Damping, _) => solver.damping = value,
+            (Field::Mass, Some(Shape::Ball(ball))) => ball.set_mass(value),
+            (Field::Radius, Some(Shape::Ball(ball))) => ball.radius = value,
+            (Field::Elasticity, Some(Shape::Ball(ball))) => ball.elasticity = value,
+            (Field::Elasticity, Some(Shape::Line(line))) => line.elasticity = value,
+            (Field::Friction, Some(Shape::Ball(ball))) => ball.friction = value,
+            (Field::Friction, Some(Shape::Line(line))) => line.friction = value,
+            _ => {}
+        }
+    }
+
+    fn text(self, value: f32) -> String {
+        match self {
+            Field::Integrator => format!("{:?}", TimeIntegrator::ALL[value as usize]),
+            Field::Substeps | Field::Iterations => format!("{}", value as u32),
+            Field::Dt => format!("{:.4}", value),
+            _ => format!("{:.2}", value),
+        }
+    }
+}
+
+// where along the slider `value` sits, from 0 to 1
+fn fraction(value: f32, (min, max, scale): (f32, f32, Scale)) -> f32 {
+    let fraction = match scale {
+        Scale::Log => (value / min).ln() / (max / min).ln(),
+        _ => (value - min) / (max - min),
+    };
+    fraction.clamp(0., 1.)
+}
+
+fn from_fraction(fraction: f32, (min, max, scale): (f32, f32, Scale)) -> f32 {
+    match scale {
+        Scale::Linear => min + (max - min) * fraction,
+        Scale::Log => min * (max / min).powf(fraction),
+        Scale::Integer => (min + (max - min) * fraction).round(),
+    }
+}
+
+fn body_action(index: usize, shape: &Shape) -> Action {
+    match shape {
+        Shape::Ball(ball) => Action::Body {
+            index,
+            mass: ball.mass,
+            radius: ball.radius,
+            elasticity: ball.elasticity,
+            friction: ball.friction,
+        },
+        Shape::Line(line) => Action::Body {
+            index,
+            mass: 0.,
+            radius: 0.,
+            elasticity: line.elasticity,
+            friction: line.friction,
+        },
+    }
+}
+
+// In-window panel with sliders for the solver settings and the selected shape's properties, O shows and
+// hides it. Changes come out as actions so that they are recorded and replayed like key presses.
+pub struct Panel {
+    pub settings: Settings,
+    // the file given with `--solver` and the solver settings last written to it, changes made with the panel
+    // are kept there when there is one
+    pub solver_file: Option<(PathBuf, SolverSettings)>,
+    // row of the slider being dragged
+    dragging: Option<usize>,
+    // whether `settings` changed since they were last saved
+    changed: bool,
+    // whether the current drag has changed anything yet, and whether this frame's change was its first
+    drag_edited: bool,
+    first_edit: bool,
+}
+
+impl Panel {
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            settings,
+            solver_file: None,
+            dragging: None,
+            changed: false,
+            drag_edited: false,
+            first_edit: false,
+        }
+    }
+
+    fn fields(scene: &Scene, selected: Option<usize>) -> Vec<Field> {
+        let mut fields = Field::SOLVER.to_vec();
+        match selected.and_then(|index| scene.shapes.get(index)) {
+            Some(Shape::Ball(_)) => fields.extend(Field::BALL),
+            Some(Shape::Line(_)) => fields.extend(Field::LINE),
+            None => {}
+        }
+        fields
+    }
+
+    // top left corner, the panel sits in the bottom left of the window
+    fn origin(rows: usize) -> Vector2<f32> {
+        let height = (rows + 2) as f32 * ROW_HEIGHT;
+        vector![MARGIN, screen_height() - height - MARGIN]
+    }
+
+    fn row_position(rows: usize, row: usize) -> Vector2<f32> {
+        // the title takes the first row and a gap is left before the selected shape's rows
+        let gap = if row >= Field::SOLVER.len() { 1 } else { 0 };
+        Self::origin(rows) + vector![0., (row + 1 + gap) as f32 * ROW_HEIGHT]
+    }
+
+    // true when `point` (in screen coordinates) is over the panel, so clicks there don't reach the scene
+    pub fn contains(&self, scene: &Scene, selected: Option<usize>, point: Vector2<f32>) -> bool {
+        if !self.settings.open {
+            return false;
+        }
+        let rows = Self::fields(scene, selected).len();
+        let origin = Self::origin(rows);
+        let height = screen_height() - MARGIN - origin.y;
+        self.dragging.is_some()
+            || (point.x >= origin.x
+                && point.x <= origin.x + WIDTH
+                && point.y >= origin.y
+                && point.y <= origin.y + height)
+    }
+
+    // true on the frame a slider drag first changes something, so an undo step can be taken for the whole drag
+    pub fn first_edit(&self) -> bool {
+        self.first_edit
+    }
+
+    // call once per frame, returns the changes made to the scene
+    pub fn update(&mut self, scene: &Scene, selected: Option<usize>) -> Vec<Action> {
+        self.first_edit = false;
+        if is_key_pressed(KeyCode::O) {
+            self.settings.open = !self.settings.open;
+            self.changed = true;
+        }
+        let mut actions = Vec::new();
+        if !self.settings.open {
+            self.dragging = None;
+            return actions;
+        }
+
+        let fields = Self::fields(scene, selected);
+        let (mx, my) = mouse_position();
+        if is_mouse_button_pressed(MouseButton::Left) {
+            self.dragging = (0..fields.len()).find(|&row| {
+                let position = Self::row_position(fields.len(), row);
+                mx >= position.x + LABEL_WIDTH
+                    && mx <= position.x + WIDTH - VALUE_WIDTH
+                    && my >= position.y
+                    && my <= position.y + ROW_HEIGHT
+            });
+            self.drag_edited = false;
+        }
+        if !is_mouse_button_down(MouseButton::Left) {
+            self.dragging = None;
+        }
+        // the rows for the selected shape go away if it is deselected mid drag
+        let Some((row, field)) = self
+            .dragging
+            .and_then(|row| fields.get(row).map(|field| (row, *field)))
+        else {
+            return actions;
+        };
+        let x = Self::row_position(fields.len(), row).x + LABEL_WIDTH;
+        let value = from_fraction(
+            ((mx - x) / (WIDTH - LABEL_WIDTH - VALUE_WIDTH)).clamp(0., 1.),
+            field.range(),
+        );
+        let original = selected.and_then(|index| scene.shapes.get(index));
+        if value == field.value(&scene.settings, original) {
+            return actions;
+        }
+        let mut solver = scene.settings;
+        let mut shape = original.cloned();
+        field.set(value, &mut solver, shape.as_mut());
+        if Field::SOLVER.contains(&field) {
+            actions.push(Action::Settings(solver));
+        } else if let (Some(index), Some(shape)) = (selected, shape) {
+            actions.push(body_action(index, &shape));
+        }
+        self.first_edit = !self.drag_edited;
+        self.drag_edited = true;
+        actions
+    }
+
+    // Writes the settings out once a change is finished, so dragging a slider doesn't write every frame.
+    // `solver` is what the scene ended up with, which also picks up dt changes made with M and N, and is only
+    // written to the `--solver` file.
+    pub fn save_if_changed<P: AsRef<Path>>(&mut self, solver: &SolverSettings, path: P) {
+        if self.dragging.is_some() {
+            return;
+        }
+        if let Some((solver_path, saved)) = &mut self.solver_file {
+            if *saved != *solver {
+                *saved = *solver;
+                if let Err(err) = solver.save(&solver_path) {
+                    eprintln!(
+                        "could not save solver settings {}: {}",
+                        solver_path.display(),
+                        err
+                    );
+                }
+            }
+        }
+        if !self.changed {
+            return;
+        }
+        self.changed = false;
+        if let Err(err) = self.settings.save(&path) {
+            eprintln!(
+                "could not save settings {}: {}",
+                path.as_ref().display(),
+                err
+            );
+        }
+    }
+
+    pub fn draw(&self, scene: &Scene, selected: Option<usize>) {
+        if !self.settings.open {
+            return;
+        }
+        let fields = Self::fields(scene, selected);
+        let origin = Self::origin(fields.len());
+        let height = screen_height() - MARGIN - origin.y;
+        draw_rectangle(
+            origin.x,
+            origin.y,
+            WIDTH,
+            height,
+            Color::new(0., 0., 0., 0.6),
+        );
+        draw_text(
+            "settings (O hides)",
+            origin.x + 8.,
+            origin.y + 16.,
+            20.,
+            WHITE,
+        );
+
+        let shape = selected.and_then(|index| scene.shapes.get(index));
+        if let Some(index) = selected.filter(|_| shape.is_some()) {
+            let position = Self::row_position(fields.len(), Field::SOLVER.len());
+            draw_text(
+                format!("shape {}", index).as_str(),
+                position.x + 8.,
+                position.y - 6.,
+                20.,
+                WHITE,
+            );
+        }
+        let track_width = WIDTH - LABEL_WIDTH - VALUE_WIDTH;
+        for (row, field) in fields.iter().enumerate() {
+            let position = Self::row_position(fields.len(), row);
+            let value = field.value(&scene.settings, shape);
+            let y = position.y + ROW_HEIGHT / 2.;
+            let color = if self.dragging == Some(row) {
+                YELLOW
+            } else {
+                WHITE
+            };
+            draw_text(field.label(), position.x + 8., y + 5., 18., color);
+            let x = position.x + LABEL_WIDTH;
+            draw_line(x, y, x + track_width, y, 2., GRAY);
+            let handle = x + fraction(value, field.range()) * track_width;
+            draw_rectangle(handle - 3., y - 7., 6., 14., color);
+            draw_text(
+                field.text(value).as_str(),
+                x + track_width + 8.,
+                y + 5.,
+                18.,
+                color,
+            );
+        }
+    }
+}
@@ -2,6 +2,7 @@ use std::{cmp, f32::NEG_INFINITY};
 
 use generational_arena::Arena;
 use na::{vector, Matrix1, UnitVector2, Vector, Vector1, Vector2};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     shapes::{Ball, Line, Shape},
@@ -12,10 +13,46 @@ use crate::{
 pub struct Collision {
     pub translate_by: Vector2<f32>,
     pub vf: Vector2<f32>,
+    // index of the shape that was hit
+    pub other: usize,
+    // contact point, unit normal pointing towards `other` and how far the shapes overlap
+    pub point: Vector2<f32>,
+    pub normal: Vector2<f32>,
+    pub depth: f32,
+    // change in momentum of this shape
+    pub impulse: Vector2<f32>,
+    // change in angular velocity from friction, only ever non-zero for balls that roll
+    pub spin: f32,
 }
 impl Collision {
-    pub fn new(translate_by: Vector2<f32>, vf: Vector2<f32>) -> Self {
-        Self { translate_by, vf }
+    pub fn new(translate_by: Vector2<f32>, vf: Vector2<f32>, other: usize) -> Self {
+        Self {
+            translate_by,
+            vf,
+            other,
+            point: vector![0., 0.],
+            normal: vector![0., 0.],
+            depth: 0.,
+            impulse: vector![0., 0.],
+            spin: 0.,
+        }
+    }
+
+    pub fn at(mut self, point: Vector2<f32>, normal: Vector2<f32>, depth: f32) -> Self {
+        self.point = point;
+        self.normal = normal.normalize();
+        self.depth = depth;
+        self
+    }
+
+    pub fn with_impulse(mut self, impulse: Vector2<f32>) -> Self {
+        self.impulse = impulse;
+        self
+    }
+
+    pub fn with_spin(mut self, spin: f32) -> Self {
+        self.spin = spin;
+        self
     }
 }
 
@@ -24,7 +61,7 @@ pub trait ForceGenerator {
     fn accumulate(&self, entity_state: &EntityState, force: &Vector2<f32>) -> Vector2<f32>;
     fn get_entity_idx(&self) -> usize;
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PointForceGenerator {
     // Applies acceleration of strength `strength` to any object in scene, oriented towards the position of the force generator
     pub strength: f32,
@@ -53,7 +90,7 @@ impl ForceGenerator for PointForceGenerator {
     }
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct SpringForceGenerator {
     pub k: f32,
     pub b: f32,
@@ -72,7 +109,7 @@ impl ForceGenerator for SpringForceGenerator {
     }
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct ObjectForceGenerator {
     // apply force to object
     pub strength: f32,
@@ -103,6 +140,58 @@ impl ForceGenerator for ObjectForceGenerator {
     }
 }
 
+// Force generators that are part of a scene (and so can be saved and loaded), as opposed to
+// the ones built every frame like gravity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Force {
+    Object(ObjectForceGenerator),
+    Point(PointForceGenerator),
+    Spring(SpringForceGenerator),
+    // `force` only acts while its ball overlaps `sensor` (an index into `scene.sensors`)
+    Region { sensor: usize, force: Box<Force> },
+}
+
+impl Force {
+    pub fn generator(&self) -> Box<dyn ForceGenerator> {
+        match self {
+            Force::Object(force) => Box::new(force.clone()),
+            Force::Point(force) => Box::new(force.clone()),
+            Force::Spring(force) => Box::new(force.clone()),
+            Force::Region { force, .. } => force.generator(),
+        }
+    }
+
+    pub fn entity_idx(&self) -> usize {
+        match self {
+            Force::Object(force) => force.entity_idx,
+            Force::Point(force) => force.entity_idx,
+            Force::Spring(force) => force.entity_idx,
+            Force::Region { force, .. } => force.entity_idx(),
+        }
+    }
+
+    pub fn entity_idx_mut(&mut self) -> &mut usize {
+        match self {
+            Force::Object(force) => &mut force.entity_idx,
+            Force::Point(force) => &mut force.entity_idx,
+            Force::Spring(force) => &mut force.entity_idx,
+            Force::Region { force, .. } => force.entity_idx_mut(),
+        }
+    }
+
+    // sensors the force is limited to, innermost last
+    pub fn sensors(&self) -> Vec<usize> {
+        match self {
+            Force::Region { sensor, force } => {
+                let mut sensors = vec![*sensor];
+                sensors.extend(force.sensors());
+                sensors
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
 // pub fn gforce(mass: f32) -> Vector2<f32> {
 //     let gravity_generator = ObjectForceGenerator::new(10., vector![0., 1.]);
 //     mass * gravity_generator.force()
@@ -124,82 +213,147 @@ pub fn calc_pos(current_pos: &Vector2<f32>, vel: &Vector2<f32>, dt: f32) -> Vect
     current_pos + vel * dt
 }
 
-pub fn interpolate_mouse_force(
-    ball_position: Vector2<f32>,
-    mouse_position: Vector2<f32>,
-    current_force: Vector2<f32>,
-    max_speed: f32,
-    damping: f32,
-) -> Vector2<f32> {
-    let direction = mouse_position - ball_position;
-    let distance = direction.magnitude();
-
-    // Normalize the direction
-    let normalized_direction = if distance != 0.0 {
-        direction / distance
-    } else {
-        Vector2::zeros()
-    };
-
-    // Interpolating force towards the direction of the mouse
-    let desired_speed = max_speed * (1.0 - (-distance / 100.0).exp()); // An exponential function to control acceleration
-    let desired_force = normalized_direction * desired_speed;
-
-    // Apply damping to current force for smooth deceleration
-    current_force + (desired_force - current_force) * damping
+// Soft spring-damper between the cursor and the point a body was grabbed at. The force is capped at
+// `max_force` so a fast drag can't yank a body through a wall or tear it out of its constraints, and since it
+// is just another force, collisions and constraints keep acting on the body while it is dragged.
+#[derive(Debug, Clone)]
+pub struct MouseJoint {
+    pub entity_idx: usize,
+    // grabbed point relative to the body's position
+    pub anchor: Vector2<f32>,
+    pub target: Vector2<f32>,
+    pub stiffness: f32,
+    pub damping: f32,
+    pub max_force: f32,
 }
 
-fn elastic_collision_velocity_mag(m_a: f32, m_b: f32, u_a: f32, u_b: f32, c_r: f32) -> (f32, f32) {
-    // https://en.wikipedia.org/wiki/Inelastic_collision
-
-    let ma_ua = m_a * u_a;
-    let mb_ub = m_b * u_b;
-    let mass_recipricol = 1. / (m_a + m_b);
-
-    let v_a = c_r * (m_b * (u_b - u_a) + ma_ua + mb_ub) * mass_recipricol;
-    let v_b = c_r * (m_a * (u_a - u_b) + ma_ua + mb_ub) * mass_recipricol;
-
-    (v_a, v_b)
+impl MouseJoint {
+    // Tuned through the spring's natural frequency (Hz) and damping ratio so that it feels the same
+    // whatever the mass, with a force cap of `max_acceleration` times the mass
+    pub fn new(entity_idx: usize, anchor: Vector2<f32>, target: Vector2<f32>, mass: f32) -> Self {
+        let frequency = 5.;
+        let damping_ratio = 0.7;
+        // keeps the speed a drag can reach (see `accumulate`) under about 10 m/s
+        let max_acceleration = 440.;
+        let omega = 2. * std::f32::consts::PI * frequency;
+        Self {
+            entity_idx,
+            anchor,
+            target,
+            stiffness: mass * omega * omega,
+            damping: 2. * mass * damping_ratio * omega,
+            max_force: max_acceleration * mass,
+        }
+    }
 }
 
-pub fn elastic_collision_velocity(ball_a: &Ball, ball_b: &Ball) -> (Vector2<f32>, Vector2<f32>) {
-    // I don't know why I didn't do on vectors directly, will change later, idk if the equations will work if you just plug the vectors in.
+impl ForceGenerator for MouseJoint {
+    fn accumulate(&self, state: &EntityState, force: &Vector2<f32>) -> Vector2<f32> {
+        let stretch = self.target - (state.position + self.anchor);
+        // only the spring is capped, the damping then limits the speed to about `max_force / damping`
+        let pull = (self.stiffness * stretch).cap_magnitude(self.max_force);
+        force + pull - self.damping * state.velocity
+    }
+    fn get_entity_idx(&self) -> usize {
+        self.entity_idx
+    }
+}
 
+// Velocities of two balls after they collide, with `normal` the unit contact normal pointing from `ball_a` to
+// `ball_b` and `c_r` the coefficient of restitution. The impulse acts along the normal only, so the tangential
+// velocities are left alone (friction is applied separately) and momentum is conserved.
+// https://en.wikipedia.org/wiki/Collision_response#Impulse-based_reaction_model
+pub fn elastic_collision_velocity(
+    ball_a: &Ball,
+    ball_b: &Ball,
+    normal: Vector2<f32>,
+    c_r: f32,
+) -> (Vector2<f32>, Vector2<f32>) {
     let u_a = ball_a.velocity;
     let u_b = ball_b.velocity;
 
     let m_a = ball_a.mass;
     let m_b = ball_b.mass;
 
-    let c_r = ball_a.elasticity.min(ball_b.elasticity);
-
-    let (va_x, vb_x) = elastic_collision_velocity_mag(m_a, m_b, u_a.x, u_b.y, c_r);
-    let (va_y, vb_y) = elastic_collision_velocity_mag(m_a, m_b, u_a.y, u_b.y, c_r);
-
-    let va = vector![va_x, va_y];
-    let vb = vector![vb_x, vb_y];
+    // closing speed along the normal, already separating balls are only pushed apart by the position correction
+    let approach = (u_a - u_b).dot(&normal);
+    if approach <= 0. {
+        return (u_a, u_b);
+    }
 
-    (va, vb)
+    let j = (1. + c_r) * approach / (1. / m_a + 1. / m_b);
+    (u_a - j / m_a * normal, u_b + j / m_b * normal)
 }
 
+// The ball's velocity and change in angular velocity after hitting a wall, `normal` pointing from the ball
+// towards the wall
 pub fn wall_collision_velocity(
     normal: Vector2<f32>,
     c_r: f32,
-    friction: f32,
-    dt: f32,
+    static_friction: f32,
+    kinetic_friction: f32,
+    restitution_threshold: f32,
     ball: &Ball,
-) -> Vector2<f32> {
-    let mut vn = normal.normalize() * normal.normalize().dot(&ball.velocity);
-
-    let mut vt = ball.velocity - vn; // tangent v
+) -> (Vector2<f32>, f32) {
     let unit_normal = normal.normalize();
-    let delta = unit_normal * ball.velocity.dot(&unit_normal);
-
-    vn = -c_r * vn; // reverse and apply elasticity
+    // a ball already moving away from the wall is only pushed out by the position correction
+    let approach = unit_normal.dot(&ball.velocity);
+    if approach <= 0. {
+        return (ball.velocity, 0.);
+    }
+    let vn = unit_normal * approach;
+    let vt = ball.velocity - vn; // tangent v
 
-    vt *= (-friction * dt).exp();
+    // slow contacts don't bounce, otherwise a ball resting on a wall keeps hopping off it and friction only
+    // catches it every other step
+    let c_r = if vn.magnitude() < restitution_threshold {
+        0.
+    } else {
+        c_r
+    };
+    // the wall doesn't move, so the normal impulse is all the ball's
+    let normal_impulse = ball.mass * (1. + c_r) * vn.magnitude();
+    // friction acts on the sliding of the contact point, which for a rolling ball includes its spin
+    let arm = unit_normal * ball.radius;
+    let inverse_inertia = ball.contact_inverse_inertia();
+    let slip = vt + ball.angular_velocity * vector![-arm.y, arm.x];
+    let friction_impulse = coulomb_friction_impulse(
+        slip,
+        normal_impulse,
+        1. / ball.mass + inverse_inertia * ball.radius * ball.radius,
+        static_friction,
+        kinetic_friction,
+    );
+
+    (
+        -c_r * vn + vt + friction_impulse / ball.mass,
+        inverse_inertia * arm.perp(&friction_impulse),
+    )
+}
 
-    vn + vt
+// Tangential impulse from Coulomb friction for a contact with relative tangential velocity `vt` and normal
+// impulse `normal_impulse`, to be applied to the body `vt` is measured for (and its opposite to the other one).
+// `inverse_mass_sum` is 1/m_a + 1/m_b, just 1/m for a wall. The contact sticks when the impulse needed to stop
+// the sliding is at most `static_friction` times the normal impulse, otherwise the sliding is slowed by
+// `kinetic_friction` times the normal impulse.
+pub fn coulomb_friction_impulse(
+    vt: Vector2<f32>,
+    normal_impulse: f32,
+    inverse_mass_sum: f32,
+    static_friction: f32,
+    kinetic_friction: f32,
+) -> Vector2<f32> {
+    let speed = vt.magnitude();
+    if speed < 1e-6 || inverse_mass_sum <= 0. {
+        return vector![0., 0.];
+    }
+    let stop = speed / inverse_mass_sum;
+    if stop <= static_friction * normal_impulse {
+        -vt / inverse_mass_sum
+    } else {
+        // never more than it takes to stop, kinetic friction doesn't reverse the sliding
+        -vt / speed * (kinetic_friction * normal_impulse).min(stop)
+    }
 }
 
 // pub fn wall_collision_velocity(ball: &Ball, line: &Line, normal: Vector2<f32>) -> Vector2<f32> {
@@ -263,3 +417,124 @@ pub fn project(a: &Vector2<f32>, b: &Vector2<f32>) -> Vector2<f32> {
     }
     a * (a.dot(b)) / a.magnitude()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ball(position: Vector2<f32>, velocity: Vector2<f32>, mass: f32) -> Ball {
+        let mut ball = Ball::new_default().translate_to(position);
+        ball.velocity = velocity;
+        ball.mass = mass;
+        ball
+    }
+
+    fn assert_close(a: Vector2<f32>, b: Vector2<f32>) {
+        assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn head_on_equal_masses_swap_velocities() {
+        let a = ball(vector![0., 0.], vector![3., 0.], 1.);
+        let b = ball(vector![20., 0.], vector![-1., 0.], 1.);
+        let (va, vb) = elastic_collision_velocity(&a, &b, vector![1., 0.], 1.);
+        assert_close(va, vector![-1., 0.]);
+        assert_close(vb, vector![3., 0.]);
+    }
+
+    #[test]
+    fn glancing_collision_with_ball_at_rest_leaves_right_angle() {
+        // equal masses, perfectly elastic: the struck ball moves off along the normal, the striker along the
+        // tangent, and their speeds add up to the incoming one the way the sides of a right triangle do
+        let a = ball(vector![0., 0.], vector![10., 0.], 1.);
+        let b = ball(vector![10., 10.], vector![0., 0.], 1.);
+        let normal = (b.position - a.position).normalize();
+        let (va, vb) = elastic_collision_velocity(&a, &b, normal, 1.);
+        assert_close(vb, vector![5., 5.]);
+        assert_close(va, vector![5., -5.]);
+        assert!(va.dot(&vb).abs() < 1e-4);
+    }
+
+    #[test]
+    fn restitution_only_scales_normal_velocity() {
+        let a = ball(vector![0., 0.], vector![4., 2.], 2.);
+        let b = ball(vector![20., 0.], vector![0., -1.], 1.);
+        let normal = vector![1., 0.];
+        let c_r = 0.5;
+        let (va, vb) = elastic_collision_velocity(&a, &b, normal, c_r);
+        // tangential velocities are untouched
+        assert!((va.y - 2.).abs() < 1e-5);
+        assert!((vb.y + 1.).abs() < 1e-5);
+        // relative normal velocity is reversed and scaled by c_r once
+        let before = (a.velocity - b.velocity).dot(&normal);
+        let after = (va - vb).dot(&normal);
+        assert!((after + c_r * before).abs() < 1e-5);
+    }
+
+    #[test]
+    fn momentum_is_conserved() {
+        let cases = [
+            (
+                vector![3., 1.],
+                vector![-2., 0.5],
+                1.,
+                4.,
+                vector![1., 1.],
+                1.,
+            ),
+            (
+                vector![0., 5.],
+                vector![1., -3.],
+                2.5,
+                0.5,
+                vector![0.3, 1.],
+                0.7,
+            ),
+            (
+                vector![-4., 2.],
+                vector![0., 0.],
+                10.,
+                1.,
+                vector![-1., 0.2],
+                0.,
+            ),
+        ];
+        for (u_a, u_b, m_a, m_b, normal, c_r) in cases {
+            let normal: Vector2<f32> = normal.normalize();
+            let a = ball(vector![0., 0.], u_a, m_a);
+            let b = ball(normal * 20., u_b, m_b);
+            let (va, vb) = elastic_collision_velocity(&a, &b, normal, c_r);
+            assert_close(m_a * va + m_b * vb, m_a * u_a + m_b * u_b);
+        }
+    }
+
+    #[test]
+    fn elastic_collision_conserves_kinetic_energy() {
+        let a = ball(vector![0., 0.], vector![7., -2.], 3.);
+        let b = ball(vector![15., 5.], vector![-1., 1.], 2.);
+        let normal = (b.position - a.position).normalize();
+        let (va, vb) = elastic_collision_velocity(&a, &b, normal, 1.);
+        let before = a.kinetic_energy() + b.kinetic_energy();
+        let after = 0.5 * a.mass * va.magnitude_squared() + 0.5 * b.mass * vb.magnitude_squared();
+        assert!((before - after).abs() < 1e-3 * before);
+    }
+
+    #[test]
+    fn separating_balls_are_left_alone() {
+        let a = ball(vector![0., 0.], vector![-1., 0.], 1.);
+        let b = ball(vector![20., 0.], vector![1., 0.], 1.);
+        let (va, vb) = elastic_collision_velocity(&a, &b, vector![1., 0.], 1.);
+        assert_close(va, a.velocity);
+        assert_close(vb, b.velocity);
+    }
+
+    #[test]
+    fn ball_leaving_a_wall_is_left_alone() {
+        // still overlapping the wall below it but already moving up and sideways, so neither a bounce nor
+        // friction should touch it
+        let a = ball(vector![0., 0.], vector![2., -1.], 1.);
+        let (velocity, spin) = wall_collision_velocity(vector![0., 1.], 1., 0.5, 0.5, 0., &a);
+        assert_close(velocity, a.velocity);
+        assert_eq!(spin, 0.);
+    }
+}
@@ -0,0 +1,169 @@
+use std::{error::Error, fs::File, io::BufWriter, path::Path};
+
+use macroquad::color::Color;
+use nalgebra::{vector, Vector2};
+
+use crate::{camera::Camera, renderer::Renderer};
+
+// An RGBA frame in memory, rows from the top, 4 bytes per pixel
+#[derive(Debug, Clone)]
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32, background: Color) -> Self {
+        let pixel: [u8; 4] = background.into();
+        Self {
+            width,
+            height,
+            pixels: pixel.repeat((width * height) as usize),
+        }
+    }
+
+    // blends `color` over the pixel, `coverage` is how much of the pixel the shape covers
+    fn blend(&mut self, x: i32, y: i32, color: Color, coverage: f32) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+        let alpha = (color.a * coverage).clamp(0., 1.);
+        if alpha <= 0. {
+            return;
+        }
+        let i = 4 * (y as usize * self.width as usize + x as usize);
+        for (channel, value) in [color.r, color.g, color.b].iter().enumerate() {
+            let old = self.pixels[i + channel] as f32 / 255.;
+            let new = value.clamp(0., 1.) * alpha + old * (1. - alpha);
+            self.pixels[i + channel] = (new * 255.).round() as u8;
+        }
+        let old = self.pixels[i + 3] as f32 / 255.;
+        self.pixels[i + 3] = ((alpha + old * (1. - alpha)) * 255.).round() as u8;
+    }
+
+    // Calls `coverage` for the centre of every pixel in the box from `min` to `max` and blends `color` in
+    // by the result, which shapes get from their signed distance so that edges are anti-aliased
+    fn fill<F: Fn(Vector2<f32>) -> f32>(
+        &mut self,
+        min: Vector2<f32>,
+        max: Vector2<f32>,
+        color: Color,
+        coverage: F,
+    ) {
+        let x_range = (min.x.floor().max(0.) as i32)..=(max.x.ceil().min(self.width as f32) as i32);
+        let y_range =
+            (min.y.floor().max(0.) as i32)..=(max.y.ceil().min(self.height as f32) as i32);
+        for y in y_range {
+            for x in x_range.clone() {
+                let amount = coverage(vector![x as f32 + 0.5, y as f32 + 0.5]);
+                self.blend(x, y, color, amount);
+            }
+        }
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let writer = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+}
+
+// 1 inside, 0 outside and a linear ramp over the pixel straddling the edge, from a signed distance
+fn coverage(distance: f32) -> f32 {
+    (0.5 - distance).clamp(0., 1.)
+}
+
+fn segment_distance(point: Vector2<f32>, start: Vector2<f32>, end: Vector2<f32>) -> f32 {
+    let d = end - start;
+    let length_squared = d.magnitude_squared();
+    let t = if length_squared > 0. {
+        ((point - start).dot(&d) / length_squared).clamp(0., 1.)
+    } else {
+        0.
+    };
+    (point - (start + d * t)).magnitude()
+}
+
+// even-odd rule
+fn inside_polygon(point: Vector2<f32>, points: &[Vector2<f32>]) -> bool {
+    let mut inside = false;
+    for (a, b) in points.iter().zip(points.iter().cycle().skip(1)) {
+        if (a.y > point.y) != (b.y > point.y) {
+            let x = a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x);
+            if point.x < x {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+// Headless backend that rasterises frames on the CPU. Text is left out since there is no font to draw it with.
+pub struct RasterRenderer {
+    camera: Camera,
+    pub canvas: Canvas,
+}
+
+impl RasterRenderer {
+    // a frame of `camera.viewport` pixels filled with `background`
+    pub fn new(camera: Camera, background: Color) -> Self {
+        let canvas = Canvas::new(
+            camera.viewport.x.round() as u32,
+            camera.viewport.y.round() as u32,
+            background,
+        );
+        Self { camera, canvas }
+    }
+}
+
+impl Renderer for RasterRenderer {
+    fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    fn circle(&mut self, center: Vector2<f32>, radius: f32, color: Color) {
+        let extent = vector![radius + 1., radius + 1.];
+        self.canvas
+            .fill(center - extent, center + extent, color, |point| {
+                coverage((point - center).magnitude() - radius)
+            });
+    }
+
+    fn circle_outline(&mut self, center: Vector2<f32>, radius: f32, thickness: f32, color: Color) {
+        let extent = vector![radius + thickness + 1., radius + thickness + 1.];
+        self.canvas
+            .fill(center - extent, center + extent, color, |point| {
+                coverage(((point - center).magnitude() - radius).abs() - thickness / 2.)
+            });
+    }
+
+    fn segment(&mut self, start: Vector2<f32>, end: Vector2<f32>, thickness: f32, color: Color) {
+        let extent = vector![thickness / 2. + 1., thickness / 2. + 1.];
+        self.canvas.fill(
+            start.inf(&end) - extent,
+            start.sup(&end) + extent,
+            color,
+            |point| coverage(segment_distance(point, start, end) - thickness / 2.),
+        );
+    }
+
+    fn polygon(&mut self, points: &[Vector2<f32>], color: Color) {
+        let Some(min) = points.iter().copied().reduce(|a, b| a.inf(&b)) else {
+            return;
+        };
+        let max = points.iter().copied().fold(min, |a, b| a.sup(&b));
+        self.canvas.fill(min, max, color, |point| {
+            if inside_polygon(point, points) {
+                1.
+            } else {
+                0.
+            }
+        });
+    }
+
+    fn text(&mut self, _text: &str, _position: Vector2<f32>, _size: f32, _color: Color) {}
+}
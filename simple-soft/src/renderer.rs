@@ -1,66 +1,551 @@
 use crate::{
-    physics::PointForceGenerator,
-    shapes::{Ball, Line},
+    camera::Camera,
+    constraints::Constraint,
+    diagnostics::{value_range, Series, HISTORY_LENGTH},
+    emitters::{Emitter, EmitterShape},
+    physics::{Collision, Force, PointForceGenerator},
+    sensors::{Sensor, SensorCount, SensorEvents, SensorShape},
+    shapes::{Ball, Line, Shape},
+    state::Scene,
 };
 use macroquad::prelude::*;
+use nalgebra::{vector, Vector2};
 
-pub fn render_line(line: &Line) {
-    draw_line(
-        line.start_point[0],
-        line.start_point[1],
-        line.end_point[0],
-        line.end_point[1],
-        5.,
-        line.color,
-    );
+// Drawing backend for the world. Backends only provide the primitives, in pixels of the window or frame
+// being drawn; the provided methods draw shapes, constraints, sensors and debug overlays through `camera()`.
+// Line widths and text sizes stay in pixels whatever the zoom.
+pub trait Renderer {
+    fn camera(&self) -> &Camera;
+
+    fn circle(&mut self, center: Vector2<f32>, radius: f32, color: Color);
+    fn circle_outline(&mut self, center: Vector2<f32>, radius: f32, thickness: f32, color: Color);
+    fn segment(&mut self, start: Vector2<f32>, end: Vector2<f32>, thickness: f32, color: Color);
+    // filled, with the points in order around the outline
+    fn polygon(&mut self, points: &[Vector2<f32>], color: Color);
+    // `position` is the left end of the baseline
+    fn text(&mut self, text: &str, position: Vector2<f32>, size: f32, color: Color);
+
+    fn line(&mut self, line: &Line) {
+        let camera = self.camera();
+        let (start, end) = (
+            camera.world_to_screen(line.start_point),
+            camera.world_to_screen(line.end_point),
+        );
+        // thermostat walls are drawn hot
+        let color = if line.temperature.is_some() {
+            ORANGE
+        } else {
+            line.color
+        };
+        self.segment(start, end, 5., color);
+    }
+
+    fn ball(&mut self, ball: &Ball) {
+        let mut color = if ball.fluid && !ball.clicked {
+            FLUID_COLOR
+        } else {
+            ball.color
+        };
+        if ball.sleeping {
+            color.a *= 0.5;
+        }
+        let camera = self.camera();
+        let (position, radius) = (
+            camera.world_to_screen(ball.position),
+            camera.screen_length(ball.radius),
+        );
+        self.circle(position, radius, color);
+        if ball.rolls {
+            // a spoke, so that it can be seen turning
+            let spoke = vector![ball.angle.cos(), ball.angle.sin()] * radius;
+            self.segment(position, position + spoke, 2., BLACK);
+        }
+    }
+
+    // springs are drawn like walls and rods as a thinner line, pins aren't drawn
+    fn constraint(&mut self, constraint: &Constraint, shapes: &[Shape]) {
+        let thickness = match constraint {
+            Constraint::Spring(_) => 5.,
+            Constraint::Distance(_) => 2.,
+            Constraint::FixedPoint(_) => return,
+            Constraint::Revolute(joint) => {
+                if let Some(anchors) = joint.anchors(shapes) {
+                    self.joint(&joint.indices(), anchors, shapes);
+                }
+                return;
+            }
+            Constraint::Prismatic(joint) => {
+                if let (Some(anchors), Some(axis)) =
+                    (joint.anchors(shapes), joint.world_axis(shapes))
+                {
+                    // the track, as far as the limits allow
+                    let (lower, upper) = joint.limits.unwrap_or((-1., 1.));
+                    let camera = self.camera();
+                    let (start, end) = (
+                        camera.world_to_screen(anchors.1 + axis * lower),
+                        camera.world_to_screen(anchors.1 + axis * upper),
+                    );
+                    self.segment(start, end, 3., GRAY);
+                    self.joint(&joint.indices(), anchors, shapes);
+                }
+                return;
+            }
+            Constraint::Wheel(joint) => {
+                if let Some((wheel, anchor)) = joint.anchors(shapes) {
+                    // the suspension, from its anchor on the chassis to the wheel's centre
+                    let camera = self.camera();
+                    let (start, end) = (
+                        camera.world_to_screen(anchor),
+                        camera.world_to_screen(wheel),
+                    );
+                    self.segment(start, end, 3., GREEN);
+                    self.joint(&joint.indices(), (wheel, anchor), shapes);
+                }
+                return;
+            }
+        };
+        if let &[index_0, index_1] = constraint.indices().as_slice() {
+            if let (Shape::Ball(ball_0), Shape::Ball(ball_1)) = (&shapes[index_0], &shapes[index_1])
+            {
+                let camera = self.camera();
+                let (start, end) = (
+                    camera.world_to_screen(ball_0.position),
+                    camera.world_to_screen(ball_1.position),
+                );
+                self.segment(start, end, thickness, BLACK);
+            }
+        }
+    }
+
+    // An arm from the centre of each jointed ball to its anchor, which shows how the ball has turned, and a
+    // ring at the anchors
+    fn joint(
+        &mut self,
+        indices: &[usize],
+        anchors: (Vector2<f32>, Vector2<f32>),
+        shapes: &[Shape],
+    ) {
+        let camera = *self.camera();
+        for (&index, anchor) in indices.iter().zip([anchors.0, anchors.1]) {
+            if let Shape::Ball(ball) = &shapes[index] {
+                let (center, anchor) = (
+                    camera.world_to_screen(ball.position),
+                    camera.world_to_screen(anchor),
+                );
+                self.segment(center, anchor, 3., BLACK);
+            }
+        }
+        self.circle_outline(camera.world_to_screen(anchors.0), 4., 2., BLACK);
+    }
+
+    // outline of the sensor, filled in while any ball is inside, labelled with its name and counts
+    fn sensor(&mut self, sensor: &Sensor, count: &SensorCount, occupied: bool) {
+        let color = Color::new(0., 1., 0.5, 0.8);
+        let fill = Color::new(0., 1., 0.5, if occupied { 0.25 } else { 0.08 });
+        let camera = *self.camera();
+        let label_at = match &sensor.shape {
+            SensorShape::Circle { center, radius } => {
+                let center = camera.world_to_screen(*center);
+                let radius = camera.screen_length(*radius);
+                self.circle(center, radius, fill);
+                self.circle_outline(center, radius, 2., color);
+                center
+            }
+            SensorShape::Polygon { points } => {
+                if points.is_empty() {
+                    return;
+                }
+                let points: Vec<Vector2<f32>> = points
+                    .iter()
+                    .map(|point| camera.world_to_screen(*point))
+                    .collect();
+                self.polygon(&points, fill);
+                for (a, b) in points.iter().zip(points.iter().cycle().skip(1)) {
+                    self.segment(*a, *b, 2., color);
+                }
+                points.iter().sum::<Vector2<f32>>() / points.len() as f32
+            }
+            SensorShape::Band {
+                start,
+                end,
+                half_width,
+            } => {
+                let start = camera.world_to_screen(*start);
+                let end = camera.world_to_screen(*end);
+                let width = camera.screen_length(2. * half_width);
+                self.segment(start, end, width, fill);
+                self.segment(start, end, 2., color);
+                (start + end) / 2.
+            }
+        };
+        self.text(
+            format!(
+                "{} in {} out {} flux {}",
+                sensor.name, count.entered, count.left, count.flux
+            )
+            .as_str(),
+            label_at,
+            16.,
+            color,
+        );
+    }
+
+    // where the emitter spawns particles, with an arrow along its direction
+    fn emitter(&mut self, emitter: &Emitter) {
+        let color = Color::new(1., 0.6, 0., 0.8);
+        let camera = *self.camera();
+        match &emitter.shape {
+            EmitterShape::Point { position } => {
+                self.circle_outline(camera.world_to_screen(*position), 5., 2., color);
+            }
+            EmitterShape::Line { start, end } => {
+                let start = camera.world_to_screen(*start);
+                let end = camera.world_to_screen(*end);
+                self.segment(start, end, 2., color);
+            }
+            EmitterShape::Area { min, max } => {
+                let corners = [
+                    vector![min.x, min.y],
+                    vector![max.x, min.y],
+                    vector![max.x, max.y],
+                    vector![min.x, max.y],
+                ]
+                .map(|corner| camera.world_to_screen(corner));
+                for (a, b) in corners.iter().zip(corners.iter().cycle().skip(1)) {
+                    self.segment(*a, *b, 2., color);
+                }
+            }
+        }
+        let center = camera.world_to_screen(emitter.shape.center());
+        if let Some(direction) = emitter.direction.try_normalize(1e-6) {
+            self.arrow(center, direction * 30., color);
+        }
+        self.text(emitter.name.as_str(), center + vector![8., -8.], 16., color);
+    }
+
+    // a glow where a point force pulls its ball towards
+    fn point_force(&mut self, generator: &PointForceGenerator) {
+        let position = self.camera().world_to_screen(generator.position);
+        self.circle(position, 15., Color::new(1., 1., 1., 0.5));
+        self.circle(position, 10., Color::new(1., 1., 1., 0.2));
+        self.circle(position, 8., Color::new(0.9, 0.9, 1., 0.4));
+        self.circle(position, 5., Color::new(0.2, 0.2, 1., 0.5));
+    }
+
+    // the sensors, emitters, point forces, shapes and constraints of `scene`, the sensors, emitters and point
+    // forces behind everything else
+    fn scene(&mut self, scene: &Scene, sensor_events: &SensorEvents) {
+        for (i, sensor) in scene.sensors.iter().enumerate() {
+            let occupied = sensor_events.balls_in(i).next().is_some();
+            self.sensor(sensor, &sensor_events.count(i), occupied);
+        }
+        for emitter in &scene.emitters {
+            self.emitter(emitter);
+        }
+        for force in &scene.forces {
+            let mut force = force;
+            while let Force::Region { force: inner, .. } = force {
+                force = inner;
+            }
+            if let Force::Point(generator) = force {
+                self.point_force(generator);
+            }
+        }
+        for shape in &scene.shapes {
+            match shape {
+                Shape::Ball(ball) => self.ball(ball),
+                Shape::Line(line) => self.line(line),
+            }
+        }
+        for constraint in &scene.constraints {
+            self.constraint(constraint, &scene.shapes);
+        }
+    }
+
+    // `from` and `vector` are in pixels
+    fn arrow(&mut self, from: Vector2<f32>, vector: Vector2<f32>, color: Color) {
+        let length = vector.magnitude();
+        if length < 1. {
+            return;
+        }
+        let to = from + vector;
+        self.segment(from, to, 1.5, color);
+        let direction = vector / length;
+        let side = Vector2::new(-direction.y, direction.x);
+        let head = 6_f32.min(length / 2.);
+        let left = to - direction * head + side * head / 2.;
+        let right = to - direction * head - side * head / 2.;
+        self.polygon(&[to, left, right], color);
+    }
+
+    // `collisions` are the ones resolved by the last step
+    fn debug(&mut self, layers: &DebugLayers, scene: &Scene, collisions: &[(usize, Collision)]) {
+        let camera = *self.camera();
+        if layers.aabbs {
+            // there is no broadphase yet, every pair is tested, so these are just the boxes of each shape
+            for shape in &scene.shapes {
+                let (min, max) = shape.aabb();
+                let (min, max) = (camera.world_to_screen(min), camera.world_to_screen(max));
+                let corners = [min, vector![max.x, min.y], max, vector![min.x, max.y]];
+                for (a, b) in corners.iter().zip(corners.iter().cycle().skip(1)) {
+                    self.segment(*a, *b, 1., MAGENTA);
+                }
+            }
+        }
+        if layers.strain {
+            for constraint in &scene.constraints {
+                if let (Some(strain), &[index_0, index_1]) = (
+                    constraint.strain(&scene.shapes),
+                    constraint.indices().as_slice(),
+                ) {
+                    if let (Shape::Ball(ball_0), Shape::Ball(ball_1)) =
+                        (&scene.shapes[index_0], &scene.shapes[index_1])
+                    {
+                        let start = camera.world_to_screen(ball_0.position);
+                        let end = camera.world_to_screen(ball_1.position);
+                        self.segment(start, end, 3., strain_color(strain));
+                    }
+                }
+                if let Constraint::FixedPoint(pin) = constraint {
+                    let position = camera.world_to_screen(pin.position);
+                    self.circle_outline(position, 4., 1., WHITE);
+                }
+            }
+        }
+        for shape in &scene.shapes {
+            if let Shape::Ball(ball) = shape {
+                let position = camera.world_to_screen(ball.position);
+                if layers.velocities {
+                    self.arrow(position, ball.velocity * VELOCITY_SCALE, GREEN);
+                }
+                if layers.forces {
+                    self.arrow(position, ball.force * FORCE_SCALE, ORANGE);
+                }
+            }
+        }
+        if layers.contacts {
+            for (_, collision) in collisions {
+                let point = camera.world_to_screen(collision.point);
+                self.circle(point, 3., RED);
+                self.arrow(point, collision.normal * 15., RED);
+            }
+        }
+    }
+}
+
+// Draws straight to the window with macroquad
+pub struct ScreenRenderer<'a> {
+    camera: &'a Camera,
+}
+
+impl<'a> ScreenRenderer<'a> {
+    pub fn new(camera: &'a Camera) -> Self {
+        Self { camera }
+    }
 }
 
-pub fn render_ball(ball: &Ball) {
-    draw_circle(ball.position[0], ball.position[1], ball.radius, ball.color);
+impl Renderer for ScreenRenderer<'_> {
+    fn camera(&self) -> &Camera {
+        self.camera
+    }
+
+    fn circle(&mut self, center: Vector2<f32>, radius: f32, color: Color) {
+        draw_circle(center.x, center.y, radius, color);
+    }
+
+    fn circle_outline(&mut self, center: Vector2<f32>, radius: f32, thickness: f32, color: Color) {
+        draw_circle_lines(center.x, center.y, radius, thickness, color);
+    }
+
+    fn segment(&mut self, start: Vector2<f32>, end: Vector2<f32>, thickness: f32, color: Color) {
+        draw_line(start.x, start.y, end.x, end.y, thickness, color);
+    }
+
+    fn polygon(&mut self, points: &[Vector2<f32>], color: Color) {
+        if points.is_empty() {
+            return;
+        }
+        // fan from the first point, right for convex polygons and close enough otherwise
+        for pair in points[1..].windows(2) {
+            draw_triangle(
+                vec2(points[0].x, points[0].y),
+                vec2(pair[0].x, pair[0].y),
+                vec2(pair[1].x, pair[1].y),
+                color,
+            );
+        }
+    }
+
+    fn text(&mut self, text: &str, position: Vector2<f32>, size: f32, color: Color) {
+        draw_text(text, position.x, position.y, size, color);
+    }
 }
-pub fn render_point_force_generator(generator: &PointForceGenerator) {
-    draw_circle(
-        generator.position[0],
-        generator.position[1],
-        15.,
+
+// Draws rolling time series in a box with its top left corner at (x, y), scaled to fit the values.
+// The newest value is on the right.
+pub fn render_plot(title: &str, series: &[Series], x: f32, y: f32, width: f32, height: f32) {
+    draw_rectangle(
+        x,
+        y,
+        width,
+        height,
         Color {
-            r: 1.,
-            g: 1.,
-            b: 1.,
-            a: 0.5,
+            r: 0.,
+            g: 0.,
+            b: 0.,
+            a: 0.6,
         },
     );
-    draw_circle(
-        generator.position[0],
-        generator.position[1],
-        10.,
-        Color {
-            r: 1.,
-            g: 1.,
-            b: 1.,
-            a: 0.2,
-        },
+    draw_rectangle_lines(x, y, width, height, 1., WHITE);
+
+    let (min, max) = value_range(series);
+    let to_screen = |i: usize, value: f32| {
+        (
+            x + width * i as f32 / (HISTORY_LENGTH - 1) as f32,
+            y + height * (1. - (value - min) / (max - min)),
+        )
+    };
+    if min < 0. && max > 0. {
+        let (_, zero) = to_screen(0, 0.);
+        draw_line(x, zero, x + width, zero, 1., GRAY);
+    }
+
+    for line in series {
+        let offset = HISTORY_LENGTH - line.values.len();
+        let points: Vec<(f32, f32)> = line
+            .values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| to_screen(i + offset, *value))
+            .collect();
+        for pair in points.windows(2) {
+            draw_line(pair[0].0, pair[0].1, pair[1].0, pair[1].1, 1.5, line.color);
+        }
+    }
+
+    draw_text(title, x + 5., y + 15., 16., WHITE);
+    draw_text(
+        format!("{:.3e}", max).as_str(),
+        x + width - 80.,
+        y + 15.,
+        14.,
+        WHITE,
     );
-    draw_circle(
-        generator.position[0],
-        generator.position[1],
-        8.,
-        Color {
-            r: 0.9,
-            g: 0.9,
-            b: 1.,
-            a: 0.4,
-        },
+    draw_text(
+        format!("{:.3e}", min).as_str(),
+        x + width - 80.,
+        y + height - 5.,
+        14.,
+        WHITE,
     );
-    draw_circle(
-        generator.position[0],
-        generator.position[1],
-        5.,
+    let mut legend_x = x + 5.;
+    for line in series {
+        let label = match line.values.back() {
+            Some(value) => format!("{} {:.3e}", line.name, value),
+            None => line.name.to_string(),
+        };
+        draw_text(&label, legend_x, y + height - 5., 14., line.color);
+        legend_x += measure_text(&label, None, 14, 1.).width + 10.;
+    }
+}
+
+// Draws a histogram over 0 to `max_x` in a box with its top left corner at (x, y). Each bin is a (value,
+// expected value) pair, and the expected values are drawn over the bars as a curve to compare against.
+pub fn render_histogram(
+    title: &str,
+    bins: &[(f32, f32)],
+    max_x: f32,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+) {
+    draw_rectangle(
+        x,
+        y,
+        width,
+        height,
         Color {
-            r: 0.2,
-            g: 0.2,
-            b: 1.,
-            a: 0.5,
+            r: 0.,
+            g: 0.,
+            b: 0.,
+            a: 0.6,
         },
     );
+    draw_rectangle_lines(x, y, width, height, 1., WHITE);
+
+    let max = bins.iter().fold(0., |max: f32, (value, expected)| {
+        max.max(*value).max(*expected)
+    });
+    if bins.is_empty() || max <= 0. {
+        draw_text(title, x + 5., y + 15., 16., WHITE);
+        return;
+    }
+    // leave room for the title at the top
+    let bottom = y + height - 20.;
+    let scale = (height - 45.) / max;
+    let bar_width = width / bins.len() as f32;
+    for (i, (value, _)) in bins.iter().enumerate() {
+        let bar_height = value * scale;
+        draw_rectangle(
+            x + i as f32 * bar_width + 1.,
+            bottom - bar_height,
+            bar_width - 2.,
+            bar_height,
+            SKYBLUE,
+        );
+    }
+    let points: Vec<(f32, f32)> = bins
+        .iter()
+        .enumerate()
+        .map(|(i, (_, expected))| (x + (i as f32 + 0.5) * bar_width, bottom - expected * scale))
+        .collect();
+    for pair in points.windows(2) {
+        draw_line(pair[0].0, pair[0].1, pair[1].0, pair[1].1, 2., YELLOW);
+    }
+
+    draw_text(title, x + 5., y + 15., 16., WHITE);
+    draw_text("0", x + 2., y + height - 5., 14., WHITE);
+    let label = format!("{:.2}", max_x);
+    draw_text(
+        label.as_str(),
+        x + width - measure_text(&label, None, 14, 1.).width - 2.,
+        y + height - 5.,
+        14.,
+        WHITE,
+    );
+}
+
+// screen pixels per m/s of velocity and per newton of force for the debug arrows
+const VELOCITY_SCALE: f32 = 5.;
+const FORCE_SCALE: f32 = 3.;
+// strain at which a constraint is drawn fully blue (compressed) or red (stretched)
+const STRAIN_SATURATION: f32 = 0.2;
+// balls that are part of the fluid
+const FLUID_COLOR: Color = Color::new(0.2, 0.5, 1., 1.);
+
+// Debug overlays drawn over the scene, each one toggled on its own (F1-F5 in the app)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DebugLayers {
+    pub velocities: bool,
+    pub forces: bool,
+    pub contacts: bool,
+    pub aabbs: bool,
+    pub strain: bool,
+}
+
+impl DebugLayers {
+    pub fn any(&self) -> bool {
+        self.velocities || self.forces || self.contacts || self.aabbs || self.strain
+    }
+}
+
+// white when at rest length, shading to blue when compressed and red when stretched
+fn strain_color(strain: f32) -> Color {
+    let amount = (strain.abs() / STRAIN_SATURATION).min(1.);
+    if strain < 0. {
+        Color::new(1. - amount, 1. - amount, 1., 1.)
+    } else {
+        Color::new(1., 1. - amount, 1. - amount, 1.)
+    }
 }
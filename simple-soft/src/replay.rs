@@ -0,0 +1,125 @@
+use std::{error::Error, fs, path::Path};
+
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    state::{Scene, SolverSettings},
+    vehicle::Drive,
+};
+
+// Inputs that change the simulation. Anything that only changes what is drawn (like the FPS counter)
+// is left out since it doesn't need to be replayed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    Reset,
+    IncreaseDt,
+    DecreaseDt,
+    // from the settings panel
+    Settings(SolverSettings),
+    // new properties for a shape from the settings panel, walls only take the elasticity and friction
+    Body {
+        index: usize,
+        mass: f32,
+        radius: f32,
+        elasticity: f32,
+        friction: f32,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StepInput {
+    // cursor position while the left mouse button is held
+    #[serde(default)]
+    pub mouse: Option<Vector2<f32>>,
+    #[serde(default)]
+    pub actions: Vec<Action>,
+    // how the vehicles are driven while an arrow key is held
+    #[serde(default)]
+    pub drive: Option<Drive>,
+}
+
+impl StepInput {
+    pub fn is_empty(&self) -> bool {
+        self.mouse.is_none() && self.actions.is_empty() && self.drive.is_none()
+    }
+}
+
+// Everything needed to reproduce a run: the starting world, the input applied before each step and
+// the hash of the world after each step (see `Scene::state_hash`) to check the replay against.
+// `scene` is only stored for worlds loaded from a scene file, otherwise the world is regenerated from `seed`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u64,
+    #[serde(default)]
+    pub scene: Option<Scene>,
+    // (step, input) for the steps that had any input
+    #[serde(default)]
+    pub inputs: Vec<(u64, StepInput)>,
+    #[serde(default)]
+    pub hashes: Vec<u64>,
+}
+
+impl Replay {
+    pub fn new(seed: u64, scene: Option<Scene>) -> Self {
+        Self {
+            seed,
+            scene,
+            inputs: Vec::new(),
+            hashes: Vec::new(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        Ok(ron::from_str(&text)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn initial_scene(&self) -> Scene {
+        match &self.scene {
+            Some(scene) => scene.clone(),
+            None => Scene::new_default(self.seed),
+        }
+    }
+
+    // number of steps recorded
+    pub fn len(&self) -> u64 {
+        self.hashes.len() as u64
+    }
+
+    pub fn record(&mut self, step: u64, input: &StepInput, hash: u64) {
+        // steps are recorded in order, so anything at or after `step` belongs to a branch that was rewound
+        self.truncate(step);
+        if !input.is_empty() {
+            self.inputs.push((step, input.clone()));
+        }
+        self.hashes.push(hash);
+    }
+
+    // drop everything from `step` on
+    pub fn truncate(&mut self, step: u64) {
+        self.inputs.retain(|(input_step, _)| *input_step < step);
+        self.hashes.truncate(step as usize);
+    }
+
+    pub fn input(&self, step: u64) -> StepInput {
+        match self
+            .inputs
+            .binary_search_by_key(&step, |(input_step, _)| *input_step)
+        {
+            Ok(i) => self.inputs[i].1.clone(),
+            Err(_) => StepInput::default(),
+        }
+    }
+
+    // true when the world after `step` matches the recording
+    pub fn check(&self, step: u64, hash: u64) -> bool {
+        self.hashes.get(step as usize) == Some(&hash)
+    }
+}
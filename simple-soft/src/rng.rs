@@ -0,0 +1,74 @@
+use std::hash::{Hash, Hasher};
+
+use ::rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+// A seeded generator that is saved with the scene, so a scene saved mid-run carries on drawing the same numbers
+// when it is loaded again. It draws the same numbers as `StdRng::seed_from_u64(seed)`, which is ChaCha12 too,
+// and is stored as its seed and how many 32-bit words it has used.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "SavedRng", into = "SavedRng")]
+pub struct SeededRng {
+    seed: u64,
+    rng: ChaCha12Rng,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedRng {
+    seed: u64,
+    #[serde(default)]
+    used: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: ChaCha12Rng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl From<SavedRng> for SeededRng {
+    fn from(saved: SavedRng) -> Self {
+        let mut rng = Self::new(saved.seed);
+        rng.rng.set_word_pos(saved.used as u128);
+        rng
+    }
+}
+
+impl From<SeededRng> for SavedRng {
+    fn from(rng: SeededRng) -> Self {
+        Self {
+            seed: rng.seed,
+            used: rng.rng.get_word_pos() as u64,
+        }
+    }
+}
+
+// two generators hash the same when they have drawn the same numbers from the same seed
+impl Hash for SeededRng {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.seed.hash(state);
+        self.rng.get_word_pos().hash(state);
+    }
+}
+
+impl RngCore for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), ::rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    shapes::{default_mask, point_line_distance, Ball, Line, Shape},
+    state::{index_after_removal, Scene},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SensorShape {
+    Circle {
+        center: Vector2<f32>,
+        radius: f32,
+    },
+    // a closed polygon, the last point joins back up with the first
+    Polygon {
+        points: Vec<Vector2<f32>>,
+    },
+    // every point within `half_width` of the segment from `start` to `end`, e.g. for counting balls
+    // crossing a line
+    Band {
+        start: Vector2<f32>,
+        end: Vector2<f32>,
+        half_width: f32,
+    },
+}
+
+impl SensorShape {
+    // true when a circle of `radius` around `point` overlaps the shape
+    pub fn overlaps(&self, point: &Vector2<f32>, radius: f32) -> bool {
+        match self {
+            SensorShape::Circle {
+                center,
+                radius: sensor_radius,
+            } => (point - center).magnitude() < sensor_radius + radius,
+            SensorShape::Polygon { points } => {
+                if points.len() < 3 {
+                    return false;
+                }
+                polygon_contains(points, point)
+                    || edges(points).any(|edge| point_line_distance(&edge, point) < radius)
+            }
+            SensorShape::Band {
+                start,
+                end,
+                half_width,
+            } => point_line_distance(&Line::new(*start, *end), point) < half_width + radius,
+        }
+    }
+}
+
+fn edges(points: &[Vector2<f32>]) -> impl Iterator<Item = Line> + '_ {
+    points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(start, end)| Line::new(*start, *end))
+}
+
+// even-odd rule, casting a ray along +x
+fn polygon_contains(points: &[Vector2<f32>], point: &Vector2<f32>) -> bool {
+    let mut inside = false;
+    for (a, b) in points.iter().zip(points.iter().cycle().skip(1)) {
+        if (a.y > point.y) != (b.y > point.y) {
+            let x = a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x);
+            if point.x < x {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+// A region that reports the balls overlapping it without pushing them out. Only balls whose category is
+// in `mask` are detected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sensor {
+    #[serde(default)]
+    pub name: String,
+    pub shape: SensorShape,
+    #[serde(default = "default_mask")]
+    pub mask: u32,
+}
+
+impl Sensor {
+    pub fn detects(&self, ball: &Ball) -> bool {
+        ball.category & self.mask != 0 && self.shape.overlaps(&ball.position, ball.radius)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorPhase {
+    // the ball started overlapping the sensor this step
+    Enter,
+    // the ball was already overlapping the sensor and still is
+    Stay,
+    // the ball stopped overlapping the sensor
+    Leave,
+}
+
+// Ball `ball` (an index into `scene.shapes`) and sensor `sensor` (an index into `scene.sensors`)
+#[derive(Debug, Clone, Copy)]
+pub struct SensorEvent {
+    pub phase: SensorPhase,
+    pub sensor: usize,
+    pub ball: usize,
+    pub position: Vector2<f32>,
+}
+
+pub type SensorCallback = Box<dyn FnMut(&SensorEvent)>;
+
+// Running totals for one sensor
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SensorCount {
+    pub entered: u32,
+    pub left: u32,
+    // balls that went through a band in the direction of the `start`-`end` line's normal (see `Line::normal`),
+    // minus the ones that went the other way. Always 0 for circles and polygons.
+    pub flux: i32,
+}
+
+// Tracks which balls are in which sensor and turns the changes after each step into enter/stay/leave
+// events, sent to subscribed callbacks and queued until `drain` is called, the same way as `ContactEvents`.
+pub struct SensorEvents {
+    // (sensor, ball) -> the positions the ball entered at and was last seen at
+    inside: BTreeMap<(usize, usize), (Vector2<f32>, Vector2<f32>)>,
+    counts: Vec<SensorCount>,
+    queue: Vec<SensorEvent>,
+    callbacks: Vec<SensorCallback>,
+}
+
+impl SensorEvents {
+    pub fn new() -> Self {
+        Self {
+            inside: BTreeMap::new(),
+            counts: Vec::new(),
+            queue: Vec::new(),
+            callbacks: Vec::new(),
+        }
+    }
+
+    pub fn subscribe(&mut self, callback: SensorCallback) {
+        self.callbacks.push(callback);
+    }
+
+    pub fn drain(&mut self) -> std::vec::Drain<'_, SensorEvent> {
+        self.queue.drain(..)
+    }
+
+    // Forget which balls are inside and reset the counts, for when the world is replaced (reset, load, rewind)
+    pub fn clear(&mut self) {
+        self.inside.clear();
+        self.counts.clear();
+        self.queue.clear();
+    }
+
+    pub fn count(&self, sensor: usize) -> SensorCount {
+        self.counts.get(sensor).copied().unwrap_or_default()
+    }
+
+    // balls currently overlapping `sensor`
+    pub fn balls_in(&self, sensor: usize) -> impl Iterator<Item = usize> + '_ {
+        self.inside
+            .range((sensor, 0)..(sensor + 1, 0))
+            .map(|((_, ball), _)| *ball)
+    }
+
+    // The ball at `index` has been removed from `scene`: it leaves every sensor it was in from where it was last
+    // seen, under its index from before the removal, and the later balls are shifted down to match
+    pub fn remove_shape(&mut self, scene: &Scene, index: usize) {
+        let mut inside = BTreeMap::new();
+        let mut events = Vec::new();
+        for ((s, i), (entered_at, last_seen)) in &self.inside {
+            if let Some(i) = index_after_removal(*i, index) {
+                inside.insert((*s, i), (*entered_at, *last_seen));
+                continue;
+            }
+            if let (Some(sensor), Some(count)) = (scene.sensors.get(*s), self.counts.get_mut(*s)) {
+                count.left += 1;
+                count.flux += crossing(&sensor.shape, entered_at, last_seen);
+            }
+            events.push(SensorEvent {
+                phase: SensorPhase::Leave,
+                sensor: *s,
+                ball: *i,
+                position: *last_seen,
+            });
+        }
+        self.inside = inside;
+        for event in &events {
+            for callback in &mut self.callbacks {
+                callback(event);
+            }
+        }
+        self.queue.extend(events);
+    }
+
+    // call after each step
+    pub fn update(&mut self, scene: &Scene) {
+        self.counts
+            .resize(scene.sensors.len(), SensorCount::default());
+        let mut inside = BTreeMap::new();
+        let mut events = Vec::new();
+        for (s, sensor) in scene.sensors.iter().enumerate() {
+            for (i, shape) in scene.shapes.iter().enumerate() {
+                let ball = match shape {
+                    Shape::Ball(ball) if sensor.detects(ball) => ball,
+                    _ => continue,
+                };
+                let (phase, entered_at) = match self.inside.get(&(s, i)) {
+                    Some((entered_at, _)) => (SensorPhase::Stay, *entered_at),
+                    None => {
+                        self.counts[s].entered += 1;
+                        (SensorPhase::Enter, ball.position)
+                    }
+                };
+                inside.insert((s, i), (entered_at, ball.position));
+                events.push(SensorEvent {
+                    phase,
+                    sensor: s,
+                    ball: i,
+                    position: ball.position,
+                });
+            }
+        }
+        for ((s, i), (entered_at, last_seen)) in &self.inside {
+            if inside.contains_key(&(*s, *i)) {
+                continue;
+            }
+            // the ball may have been removed, or the sensor with it
+            let position = match scene.shapes.get(*i) {
+                Some(Shape::Ball(ball)) => ball.position,
+                _ => *last_seen,
+            };
+            if let Some(sensor) = scene.sensors.get(*s) {
+                self.counts[*s].left += 1;
+                self.counts[*s].flux += crossing(&sensor.shape, entered_at, &position);
+            }
+            events.push(SensorEvent {
+                phase: SensorPhase::Leave,
+                sensor: *s,
+                ball: *i,
+                position,
+            });
+        }
+        self.inside = inside;
+
+        for event in &events {
+            for callback in &mut self.callbacks {
+                callback(event);
+            }
+        }
+        self.queue.extend(events);
+    }
+}
+
+// +1 for going through a band along the normal of `start`-`end`, -1 the other way, 0 for leaving on the
+// side it came in
+fn crossing(shape: &SensorShape, from: &Vector2<f32>, to: &Vector2<f32>) -> i32 {
+    if let SensorShape::Band { start, end, .. } = shape {
+        let d = end - start;
+        let side_from = d.perp(&(from - start));
+        let side_to = d.perp(&(to - start));
+        // the normal points to the negative side
+        if side_from > 0. && side_to < 0. {
+            return 1;
+        }
+        if side_from < 0. && side_to > 0. {
+            return -1;
+        }
+    }
+    0
+}
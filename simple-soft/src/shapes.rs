@@ -8,22 +8,93 @@ use macroquad::{
     prelude::Color,
 };
 use na::{distance, vector, Vector, Vector2};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug)]
 pub struct Spring {}
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Ball {
     pub position: Vector2<f32>,
+    #[serde(default)]
     pub velocity: Vector2<f32>,
+    #[serde(default)]
     pub acceleration: Vector2<f32>,
+    #[serde(default)]
     pub force: Vector2<f32>,
+    // orientation in radians (clockwise on screen, since y points down) and how fast it changes. Joints turn
+    // balls, and so do contacts with balls that `rolls`.
+    #[serde(default)]
+    pub angle: f32,
+    #[serde(default)]
+    pub angular_velocity: f32,
     pub mass: f32,
     pub radius: f32,
+    #[serde(skip, default = "default_ball_color")]
     pub color: Color,
+    #[serde(skip)]
     pub clicked: bool,
+    // sleeping balls are not integrated or collision tested until something wakes them, see `sleep.rs`
+    #[serde(skip)]
+    pub sleeping: bool,
+    // how long the ball has been slow enough to sleep
+    #[serde(skip)]
+    pub sleep_time: f32,
+    // island the ball fell asleep in, the whole island wakes together
+    #[serde(skip)]
+    pub island: usize,
+    // index into `scene.emitters` of the emitter that spawned the ball, and how much longer it lives
+    #[serde(default)]
+    pub emitter: Option<usize>,
+    #[serde(default)]
+    pub lifetime: Option<f32>,
     pub elasticity: f32,
+    // Coulomb friction coefficients, `friction` while sliding and `static_friction` (the same as `friction`
+    // when left out) to start sliding
     pub friction: f32,
+    #[serde(default)]
+    pub static_friction: Option<f32>,
+    // name of an entry in `scene.materials`, which then replaces `elasticity` and `friction`
+    #[serde(default)]
+    pub material: Option<String>,
+    // collision filtering, see `layers_collide`
+    #[serde(default = "default_category")]
+    pub category: u32,
+    #[serde(default = "default_mask")]
+    pub mask: u32,
+    // a particle of the scene's fluid, see `fluid.rs`
+    #[serde(default)]
+    pub fluid: bool,
+    // friction at its contacts turns the ball as well as slowing it down, so it rolls like a wheel instead
+    // of sliding
+    #[serde(default)]
+    pub rolls: bool,
+}
+
+fn default_ball_color() -> Color {
+    WHITE
+}
+
+fn default_line_color() -> Color {
+    BLACK
+}
+
+fn default_line_mass() -> f32 {
+    INFINITY
+}
+
+// lightest a ball can be made, a massless ball would get infinite accelerations
+pub const MIN_MASS: f32 = 1e-3;
+
+// every shape is in the first category and collides with everything unless told otherwise
+pub const DEFAULT_CATEGORY: u32 = 1;
+
+fn default_category() -> u32 {
+    DEFAULT_CATEGORY
+}
+
+pub fn default_mask() -> u32 {
+    u32::MAX
 }
 
 impl Ball {
@@ -42,12 +113,25 @@ impl Ball {
             velocity,
             acceleration,
             force,
+            angle: 0.,
+            angular_velocity: 0.,
             mass,
             radius,
             color: WHITE,
             clicked: false,
+            sleeping: false,
+            sleep_time: 0.,
+            island: 0,
+            emitter: None,
+            lifetime: None,
+            fluid: false,
+            rolls: false,
             elasticity: elasticity,
             friction: friction,
+            static_friction: None,
+            material: None,
+            category: DEFAULT_CATEGORY,
+            mask: u32::MAX,
         }
     }
     pub fn new_default() -> Self {
@@ -64,14 +148,15 @@ impl Ball {
             vector![0., 0.],
             vector![0., 0.],
             1.0,
-            10.0,
             0.2,
-            5.0,
+            0.2,
+            0.3,
         )
     }
 
+    // every edit to a ball's mass goes through here so it stays positive
     pub fn set_mass(&mut self, mass: f32) {
-        self.mass = mass;
+        self.mass = mass.max(MIN_MASS);
     }
 
     pub fn translate_to(mut self, position: Vector2<f32>) -> Self {
@@ -83,17 +168,53 @@ impl Ball {
     pub fn translate_by(&mut self, delta: Vector2<f32>) {
         self.position += delta;
     }
+
+    // moment of inertia of a uniform disc about its centre
+    pub fn inertia(&self) -> f32 {
+        0.5 * self.mass * self.radius * self.radius
+    }
+
+    // 1 / inertia for contacts, which only turn balls that roll
+    pub fn contact_inverse_inertia(&self) -> f32 {
+        if self.rolls {
+            1. / self.inertia()
+        } else {
+            0.
+        }
+    }
+
+    pub fn kinetic_energy(&self) -> f32 {
+        0.5 * self.mass * self.velocity.magnitude_squared()
+            + 0.5 * self.inertia() * self.angular_velocity * self.angular_velocity
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+// color and mass are not stored in scene files, and `d` is recomputed from the end points
+// on load (see `Scene::load`) so that editing a file by hand can't leave it stale
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Line {
     pub start_point: Vector2<f32>,
     pub end_point: Vector2<f32>,
+    #[serde(skip, default = "default_line_color")]
     pub color: Color,
+    #[serde(skip)]
     pub d: Vector2<f32>,
     pub elasticity: f32,
     pub friction: f32,
+    #[serde(default)]
+    pub static_friction: Option<f32>,
+    #[serde(skip, default = "default_line_mass")]
     pub mass: f32,
+    #[serde(default)]
+    pub material: Option<String>,
+    #[serde(default = "default_category")]
+    pub category: u32,
+    #[serde(default = "default_mask")]
+    pub mask: u32,
+    // a thermostat wall: balls leave it with velocities drawn at this temperature instead of bouncing off
+    // (see `gas.rs`)
+    #[serde(default)]
+    pub temperature: Option<f32>,
 }
 
 impl Line {
@@ -104,8 +225,13 @@ impl Line {
             color: BLACK,
             d: end - start,
             elasticity: 0.8,
-            friction: 10.,
+            friction: 0.4,
+            static_friction: Some(0.6),
             mass: INFINITY,
+            material: None,
+            category: DEFAULT_CATEGORY,
+            mask: u32::MAX,
+            temperature: None,
         }
     }
 
@@ -144,12 +270,43 @@ impl Line {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Shape {
     Ball(Ball),
     Line(Line),
 }
 
+impl Shape {
+    // axis aligned bounding box as (min, max) corners
+    pub fn aabb(&self) -> (Vector2<f32>, Vector2<f32>) {
+        match self {
+            Shape::Ball(ball) => {
+                let extent = vector![ball.radius, ball.radius];
+                (ball.position - extent, ball.position + extent)
+            }
+            Shape::Line(line) => (
+                line.start_point.inf(&line.end_point),
+                line.start_point.sup(&line.end_point),
+            ),
+        }
+    }
+
+    // (category, mask): the categories the shape is in and the categories it collides with, as bitmasks
+    pub fn layers(&self) -> (u32, u32) {
+        match self {
+            Shape::Ball(ball) => (ball.category, ball.mask),
+            Shape::Line(line) => (line.category, line.mask),
+        }
+    }
+}
+
+// Two shapes collide only when each one's mask includes a category of the other
+pub fn layers_collide(a: &Shape, b: &Shape) -> bool {
+    let (category_a, mask_a) = a.layers();
+    let (category_b, mask_b) = b.layers();
+    category_a & mask_b != 0 && category_b & mask_a != 0
+}
+
 // pub fn ball_line_collision(ball: &Ball, line: &Line) -> bool {
 //     // https://stackoverflow.com/a/1084899
 //     let f = line.start_point - ball.position; // Vector from sphere center to line start point
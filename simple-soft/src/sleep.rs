@@ -0,0 +1,135 @@
+use crate::{physics::Collision, shapes::Shape, state::Scene};
+
+// Union-find over shape indices, used to group balls that touch or are constrained together into islands
+struct Islands {
+    parent: Vec<usize>,
+}
+
+impl Islands {
+    fn new(n: usize) -> Self {
+        Self {
+            parent: (0..n).collect(),
+        }
+    }
+
+    fn find(&mut self, i: usize) -> usize {
+        let mut root = i;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        // path compression
+        let mut i = i;
+        while self.parent[i] != root {
+            let next = self.parent[i];
+            self.parent[i] = root;
+            i = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent[a.max(b)] = a.min(b);
+        }
+    }
+}
+
+fn build_islands(scene: &Scene, collisions: &[(usize, Collision)]) -> Islands {
+    let mut islands = Islands::new(scene.shapes.len());
+    for constraint in &scene.constraints {
+        let indices = constraint.indices();
+        for pair in indices.windows(2) {
+            islands.union(pair[0], pair[1]);
+        }
+    }
+    for (i, collision) in collisions {
+        // walls don't join islands, otherwise everything on the floor would be one island
+        if let Shape::Ball(_) = scene.shapes[collision.other] {
+            islands.union(*i, collision.other);
+        }
+    }
+    islands
+}
+
+pub fn is_sleeping(scene: &Scene, index: usize) -> bool {
+    matches!(&scene.shapes[index], Shape::Ball(ball) if ball.sleeping)
+}
+
+// Wakes the ball at `index` and every ball that went to sleep in the same island as it
+pub fn wake(scene: &mut Scene, index: usize) {
+    let island = match &mut scene.shapes[index] {
+        Shape::Ball(ball) if ball.sleeping => ball.island,
+        Shape::Ball(ball) => {
+            ball.sleep_time = 0.;
+            return;
+        }
+        _ => return,
+    };
+    for shape in scene.shapes.iter_mut() {
+        if let Shape::Ball(ball) = shape {
+            if ball.sleeping && ball.island == island {
+                ball.sleeping = false;
+                ball.sleep_time = 0.;
+            }
+        }
+    }
+}
+
+// Islands are built from this step's contacts and the constraints. A sleeping ball that ends up in an island
+// with an awake one (something hit it, or it is constrained to something that moves) is woken along with its
+// old island. An island where every ball has been slower than `sleep_velocity` for `sleep_time` goes to sleep.
+pub fn update_sleep(scene: &mut Scene, collisions: &[(usize, Collision)], dt: f32) {
+    if !scene.settings.sleep {
+        return;
+    }
+    let mut islands = build_islands(scene, collisions);
+    let n = scene.shapes.len();
+    let roots: Vec<usize> = (0..n).map(|i| islands.find(i)).collect();
+
+    // islands with at least one ball that is awake and still moving
+    let mut active = vec![false; n];
+    for (i, shape) in scene.shapes.iter().enumerate() {
+        if let Shape::Ball(ball) = shape {
+            if !ball.sleeping && ball.sleep_time < scene.settings.sleep_time {
+                active[roots[i]] = true;
+            }
+        }
+    }
+    for i in 0..n {
+        if active[roots[i]] && is_sleeping(scene, i) {
+            wake(scene, i);
+        }
+    }
+
+    // islands where any ball is moving can't sleep
+    let mut moving = vec![false; n];
+    for (i, shape) in scene.shapes.iter_mut().enumerate() {
+        if let Shape::Ball(ball) = shape {
+            if ball.sleeping {
+                continue;
+            }
+            // a ball spinning in place isn't resting either
+            let speed = ball.velocity.magnitude() + ball.angular_velocity.abs() * ball.radius;
+            if speed < scene.settings.sleep_velocity {
+                ball.sleep_time += dt;
+            } else {
+                ball.sleep_time = 0.;
+            }
+            // fluid never sleeps, the pressure on a particle changes whenever its neighbours move
+            if ball.sleep_time < scene.settings.sleep_time || ball.clicked || ball.fluid {
+                moving[roots[i]] = true;
+            }
+        }
+    }
+    for (i, shape) in scene.shapes.iter_mut().enumerate() {
+        if let Shape::Ball(ball) = shape {
+            if !ball.sleeping && !moving[roots[i]] {
+                ball.sleeping = true;
+                ball.island = roots[i];
+                ball.velocity = nalgebra::vector![0., 0.];
+                ball.angular_velocity = 0.;
+            }
+        }
+    }
+}
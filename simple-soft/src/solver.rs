@@ -1,5 +1,6 @@
 use crate::physics::ForceGenerator;
 use nalgebra::{vector, Vector2};
+use serde::{Deserialize, Serialize};
 #[derive(Debug, Default)]
 pub struct EntityState {
     pub velocity: Vector2<f32>,
@@ -29,7 +30,37 @@ pub struct Derivative {
 //
 // TODO in real simulation, the acceleration function would be calculated based on the sum of the forces on an object
 
-pub enum TimeIntegrator {}
+// which integrator `world::step` advances the balls with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum TimeIntegrator {
+    #[default]
+    RungeKutta4,
+    // cheaper and keeps energy bounded better over long runs, but only first order accurate
+    SemiImplicitEuler,
+}
+
+impl TimeIntegrator {
+    pub const ALL: [TimeIntegrator; 2] = [
+        TimeIntegrator::RungeKutta4,
+        TimeIntegrator::SemiImplicitEuler,
+    ];
+}
+
+// v_k+1 = v_k + dt a(x_k, t_k)
+// x_k+1 = x_k + dt v_k+1
+pub fn semi_implicit_euler(
+    state: &EntityState,
+    forces: &[Box<dyn ForceGenerator>],
+    dt: f32,
+) -> (Vector2<f32>, Vector2<f32>) {
+    let mut net_force = vector![0., 0.];
+    for force in forces {
+        net_force = force.accumulate(state, &net_force);
+    }
+    let v_update = net_force / state.mass * dt;
+    let x_update = (state.velocity + v_update) * dt;
+    (x_update, v_update)
+}
 
 // fn acceleration(state: &State, time: f32) -> Vector2<f32> {
 //     let k = 15.0;
@@ -1,3 +1,410 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    error::Error,
+    fs,
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+use ::rand::{rngs::StdRng, Rng, SeedableRng};
+use nalgebra::{vector, Vector2};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constraints::{Constraint, DistanceConstraint, SpringConstraint},
+    emitters::Emitter,
+    fluid::FluidSettings,
+    gas::GasSettings,
+    langevin::Langevin,
+    materials::Materials,
+    physics::{Force, MouseJoint},
+    sensors::Sensor,
+    shapes::{Ball, Line, Shape},
+    solver::TimeIntegrator,
+    vehicle::Vehicle,
+};
+
+// missing fields take their default value, so older scene files still load
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SolverSettings {
+    pub dt: f32,
+    pub gravity: Vector2<f32>,
+    pub integrator: TimeIntegrator,
+    // each step is split into this many equal substeps
+    pub substeps: u32,
+    // passes over the rods and pins per substep, more keeps chains of them stiffer
+    pub iterations: u32,
+    // fraction of a ball's velocity lost per second, like air drag on everything
+    pub damping: f32,
+    // put islands of resting balls to sleep
+    pub sleep: bool,
+    // speed below which a ball counts as resting
+    pub sleep_velocity: f32,
+    // how long every ball in an island has to rest before it sleeps
+    pub sleep_time: f32,
+    // contacts slower than this along the normal don't bounce, so resting contacts stay resting
+    pub restitution_threshold: f32,
+}
+
+impl Default for SolverSettings {
+    fn default() -> Self {
+        Self {
+            dt: 0.01,
+            gravity: vector![0., 9.8],
+            integrator: TimeIntegrator::default(),
+            substeps: 1,
+            iterations: 1,
+            damping: 0.,
+            sleep: true,
+            sleep_velocity: 0.05,
+            sleep_time: 1.,
+            restitution_threshold: 0.5,
+        }
+    }
+}
+
+// Solver settings can also be kept in a file of their own, to run scenes with settings other than their own
+impl SolverSettings {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        Ok(ron::from_str(&text)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+// Everything needed to rebuild a world: constraints and forces refer to shapes by their index in `shapes`.
+// Lengths are in metres, masses in kilograms and times in seconds, with y pointing down.
+// Scenes are stored as RON files, e.g.
+//
+// (
+//     settings: (dt: 0.01, gravity: (0.0, 9.8)),
+//     shapes: [
+//         Ball((position: (2.0, 2.0), mass: 1.0, radius: 0.2, elasticity: 0.98, friction: 0.3)),
+//         Line((start_point: (1.0, 20.0), end_point: (20.0, 20.0), elasticity: 0.8, friction: 0.4)),
+//     ],
+//     constraints: [],
+//     forces: [],
+// )
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Scene {
-    // shapes:
+    #[serde(default)]
+    pub settings: SolverSettings,
+    #[serde(default)]
+    pub shapes: Vec<Shape>,
+    #[serde(default)]
+    pub constraints: Vec<Constraint>,
+    #[serde(default)]
+    pub forces: Vec<Force>,
+    // regions that report overlapping balls without colliding with them
+    #[serde(default)]
+    pub sensors: Vec<Sensor>,
+    // named materials shapes can use and how two of them combine, the built-in ones when left out
+    #[serde(default)]
+    pub materials: Materials,
+    // how the balls marked `fluid` behave
+    #[serde(default)]
+    pub fluid: FluidSettings,
+    // sources that keep adding balls while the scene runs
+    #[serde(default)]
+    pub emitters: Vec<Emitter>,
+    // chassis driven through the wheel joints hanging from them
+    #[serde(default)]
+    pub vehicles: Vec<Vehicle>,
+    // seeds the velocities thermostat walls give balls
+    #[serde(default)]
+    pub gas: GasSettings,
+    // thermostats that jostle balls like molecules of a surrounding fluid
+    #[serde(default)]
+    pub langevin: Vec<Langevin>,
+    // the ball being dragged with the mouse, if any
+    #[serde(skip)]
+    pub mouse_joint: Option<MouseJoint>,
+}
+
+// Where the shape at `index` ends up once the shape at `removed` is taken out of `scene.shapes`, `None` when it
+// is the one removed
+pub fn index_after_removal(index: usize, removed: usize) -> Option<usize> {
+    match index.cmp(&removed) {
+        std::cmp::Ordering::Less => Some(index),
+        std::cmp::Ordering::Equal => None,
+        std::cmp::Ordering::Greater => Some(index - 1),
+    }
+}
+
+impl Scene {
+    pub fn new_default(seed: u64) -> Self {
+        // seven random balls in a box, the first three joined by springs and the next three by rods.
+        // The same seed always gives the same scene.
+        let mut scene = Scene::default();
+        scene.generate_balls(7, &mut StdRng::seed_from_u64(seed));
+
+        let walls = [
+            (vector![20., 20.], vector![1., 20.]),
+            (vector![1., 1.], vector![20., 1.]),
+            (vector![1., 20.], vector![1., 1.]),
+            (vector![20., 1.], vector![20., 20.]),
+        ];
+        for (start, end) in walls {
+            scene.shapes.push(Shape::Line(Line::new(start, end)));
+        }
+
+        for (index_0, index_1) in [(0, 1), (1, 2), (2, 0)] {
+            scene.constraints.push(Constraint::Spring(SpringConstraint {
+                index_0,
+                index_1,
+                distance: 1.,
+                k: 50.,
+                dampen: 0.1,
+                collide_connected: false,
+            }));
+        }
+        for (index_0, index_1) in [(4, 5), (5, 6), (6, 4)] {
+            scene
+                .constraints
+                .push(Constraint::Distance(DistanceConstraint::new(
+                    index_0, index_1, 0.8,
+                )));
+        }
+        scene
+    }
+
+    pub fn generate_balls<R: Rng>(&mut self, n: u32, rng: &mut R) {
+        for _ in 0..n {
+            let mut ball = Ball::new_default();
+            let x: f32 = rng.gen_range(1.1..=18.);
+            let y: f32 = rng.gen_range(1.1..=18.);
+            ball.elasticity = 0.98;
+            self.shapes
+                .push(Shape::Ball(ball.translate_to(vector![x, y])));
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let mut scene: Scene = ron::from_str(&text)?;
+        for shape in &mut scene.shapes {
+            if let Shape::Line(line) = shape {
+                line.d = line.end_point - line.start_point;
+            }
+        }
+        scene.validate()?;
+        Ok(scene)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    // Removes the shape at `index` along with the constraints and forces acting on it, and shifts the indices
+    // of everything that referred to a later shape
+    pub fn remove_shape(&mut self, index: usize) {
+        self.shapes.remove(index);
+        self.constraints
+            .retain(|constraint| !constraint.indices().contains(&index));
+        self.forces.retain(|force| force.entity_idx() != index);
+        let shifted = |i: &mut usize| {
+            if *i > index {
+                *i -= 1;
+            }
+        };
+        for constraint in &mut self.constraints {
+            constraint.indices_mut().into_iter().for_each(shifted);
+        }
+        for force in &mut self.forces {
+            shifted(force.entity_idx_mut());
+        }
+        self.vehicles.retain(|vehicle| vehicle.chassis != index);
+        for vehicle in &mut self.vehicles {
+            shifted(&mut vehicle.chassis);
+        }
+        if self
+            .mouse_joint
+            .as_ref()
+            .is_some_and(|joint| joint.entity_idx == index)
+        {
+            self.mouse_joint = None;
+        }
+        if let Some(joint) = &mut self.mouse_joint {
+            shifted(&mut joint.entity_idx);
+        }
+    }
+
+    // Hash of the exact bit patterns of everything a step reads and changes: every shape's motion and sleep
+    // state, the particles' lifetimes, and how far the emitters and thermostats are through their random
+    // numbers. Two runs that hash the same after every step went through the same states.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.shapes.len().hash(&mut hasher);
+        for shape in &self.shapes {
+            match shape {
+                Shape::Ball(ball) => {
+                    let values = ball.position.iter().chain(ball.velocity.iter()).chain([
+                        &ball.angle,
+                        &ball.angular_velocity,
+                        &ball.mass,
+                        &ball.radius,
+                        &ball.sleep_time,
+                    ]);
+                    for value in values {
+                        value.to_bits().hash(&mut hasher);
+                    }
+                    ball.sleeping.hash(&mut hasher);
+                    ball.island.hash(&mut hasher);
+                    ball.emitter.hash(&mut hasher);
+                    ball.lifetime.map(f32::to_bits).hash(&mut hasher);
+                }
+                Shape::Line(line) => {
+                    for value in line.start_point.iter().chain(line.end_point.iter()) {
+                        value.to_bits().hash(&mut hasher);
+                    }
+                }
+            }
+        }
+        for emitter in &self.emitters {
+            emitter.state.hash(&mut hasher);
+        }
+        self.gas.hash(&mut hasher);
+        self.langevin.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn is_ball(&self, index: usize) -> bool {
+        matches!(self.shapes.get(index), Some(Shape::Ball(_)))
+    }
+
+    // Constraints and forces index straight into `shapes`, so a bad index in a hand-edited file
+    // would otherwise only show up as a panic in the middle of the simulation
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        for (i, shape) in self.shapes.iter().enumerate() {
+            let material = match shape {
+                Shape::Ball(ball) => &ball.material,
+                Shape::Line(line) => &line.material,
+            };
+            if let Some(name) = material {
+                if self.materials.get(name).is_none() {
+                    return Err(format!("shape {} uses unknown material {}", i, name).into());
+                }
+            }
+        }
+        for (i, constraint) in self.constraints.iter().enumerate() {
+            if let Some(index) = constraint
+                .indices()
+                .into_iter()
+                .find(|&index| !self.is_ball(index))
+            {
+                return Err(format!(
+                    "constraint {} refers to shape {} which is not a ball",
+                    i, index
+                )
+                .into());
+            }
+        }
+        for (i, emitter) in self.emitters.iter().enumerate() {
+            if let Some(name) = &emitter.material {
+                if self.materials.get(name).is_none() {
+                    return Err(format!("emitter {} uses unknown material {}", i, name).into());
+                }
+            }
+            if let Some(sensor) = emitter
+                .despawn_sensors
+                .iter()
+                .chain(
+                    emitter
+                        .forces
+                        .iter()
+                        .flat_map(|force| force.sensors())
+                        .collect::<Vec<_>>()
+                        .iter(),
+                )
+                .find(|&&sensor| sensor >= self.sensors.len())
+            {
+                return Err(format!("emitter {} refers to missing sensor {}", i, sensor).into());
+            }
+        }
+        // a ball under two thermostats would be kicked and slowed twice
+        let categories = self
+            .shapes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, shape)| match shape {
+                Shape::Ball(ball) => Some((format!("ball {}", i), ball.category)),
+                Shape::Line(_) => None,
+            })
+            .chain(
+                self.emitters
+                    .iter()
+                    .enumerate()
+                    .map(|(i, emitter)| (format!("emitter {}", i), emitter.category)),
+            );
+        for (name, category) in categories {
+            let thermostats: Vec<usize> = (0..self.langevin.len())
+                .filter(|&k| self.langevin[k].covers(category))
+                .collect();
+            if thermostats.len() > 1 {
+                return Err(format!(
+                    "{} is under more than one thermostat: {:?}",
+                    name, thermostats
+                )
+                .into());
+            }
+        }
+        for (i, vehicle) in self.vehicles.iter().enumerate() {
+            if !self.is_ball(vehicle.chassis) {
+                return Err(format!(
+                    "vehicle {} has shape {} as its chassis, which is not a ball",
+                    i, vehicle.chassis
+                )
+                .into());
+            }
+        }
+        for (i, force) in self.forces.iter().enumerate() {
+            let index = force.entity_idx();
+            if !self.is_ball(index) {
+                return Err(
+                    format!("force {} refers to shape {} which is not a ball", i, index).into(),
+                );
+            }
+            if let Some(sensor) = force
+                .sensors()
+                .into_iter()
+                .find(|&sensor| sensor >= self.sensors.len())
+            {
+                return Err(format!("force {} refers to missing sensor {}", i, sensor).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::langevin;
+
+    #[test]
+    fn state_hash_sees_spin_and_random_streams() {
+        let scene = Scene::load("scenes/diffusion.ron").unwrap();
+
+        let mut spun = scene.clone();
+        let ball = spun.shapes.iter_mut().find_map(|shape| match shape {
+            Shape::Ball(ball) => Some(ball),
+            _ => None,
+        });
+        ball.unwrap().angular_velocity += 1.;
+        assert_ne!(scene.state_hash(), spun.state_hash());
+
+        // drawing kicks moves the thermostats' generators on without touching any ball
+        let mut kicked = scene.clone();
+        langevin::forces(&mut kicked, scene.settings.dt);
+        assert_ne!(scene.state_hash(), kicked.state_hash());
+        assert_eq!(scene.state_hash(), scene.clone().state_hash());
+    }
 }
@@ -0,0 +1,135 @@
+use std::{error::Error, fmt::Write as _, fs, path::Path};
+
+use macroquad::color::Color;
+use nalgebra::Vector2;
+
+use crate::{camera::Camera, renderer::Renderer};
+
+// Headless backend that builds each frame as an SVG document, one element per primitive
+pub struct SvgRenderer {
+    camera: Camera,
+    body: String,
+}
+
+impl SvgRenderer {
+    // a frame of `camera.viewport` pixels filled with `background`
+    pub fn new(camera: Camera, background: Color) -> Self {
+        let mut body = String::new();
+        let _ = writeln!(
+            body,
+            r#"<rect width="100%" height="100%" {}/>"#,
+            fill(background)
+        );
+        Self { camera, body }
+    }
+
+    pub fn finish(self) -> String {
+        format!(
+            concat!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+                "\n{}</svg>\n"
+            ),
+            self.body,
+            w = self.camera.viewport.x.round(),
+            h = self.camera.viewport.y.round(),
+        )
+    }
+
+    pub fn save<P: AsRef<Path>>(self, path: P) -> Result<(), Box<dyn Error>> {
+        fs::write(path, self.finish())?;
+        Ok(())
+    }
+}
+
+fn rgb(color: Color) -> String {
+    format!(
+        "rgb({},{},{})",
+        (color.r.clamp(0., 1.) * 255.).round(),
+        (color.g.clamp(0., 1.) * 255.).round(),
+        (color.b.clamp(0., 1.) * 255.).round()
+    )
+}
+
+fn fill(color: Color) -> String {
+    format!(r#"fill="{}" fill-opacity="{:.3}""#, rgb(color), color.a)
+}
+
+fn stroke(color: Color, thickness: f32) -> String {
+    format!(
+        r#"fill="none" stroke="{}" stroke-opacity="{:.3}" stroke-width="{:.2}""#,
+        rgb(color),
+        color.a,
+        thickness
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+impl Renderer for SvgRenderer {
+    fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    fn circle(&mut self, center: Vector2<f32>, radius: f32, color: Color) {
+        let _ = writeln!(
+            self.body,
+            r#"<circle cx="{:.2}" cy="{:.2}" r="{:.2}" {}/>"#,
+            center.x,
+            center.y,
+            radius,
+            fill(color)
+        );
+    }
+
+    fn circle_outline(&mut self, center: Vector2<f32>, radius: f32, thickness: f32, color: Color) {
+        let _ = writeln!(
+            self.body,
+            r#"<circle cx="{:.2}" cy="{:.2}" r="{:.2}" {}/>"#,
+            center.x,
+            center.y,
+            radius,
+            stroke(color, thickness)
+        );
+    }
+
+    fn segment(&mut self, start: Vector2<f32>, end: Vector2<f32>, thickness: f32, color: Color) {
+        let _ = writeln!(
+            self.body,
+            r#"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}" {}/>"#,
+            start.x,
+            start.y,
+            end.x,
+            end.y,
+            stroke(color, thickness)
+        );
+    }
+
+    fn polygon(&mut self, points: &[Vector2<f32>], color: Color) {
+        let points: Vec<String> = points
+            .iter()
+            .map(|point| format!("{:.2},{:.2}", point.x, point.y))
+            .collect();
+        let _ = writeln!(
+            self.body,
+            r#"<polygon points="{}" {}/>"#,
+            points.join(" "),
+            fill(color)
+        );
+    }
+
+    fn text(&mut self, text: &str, position: Vector2<f32>, size: f32, color: Color) {
+        let _ = writeln!(
+            self.body,
+            r#"<text x="{:.2}" y="{:.2}" font-family="monospace" font-size="{:.1}" {}>{}</text>"#,
+            position.x,
+            position.y,
+            size,
+            fill(color),
+            escape(text)
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use circular_buffer::CircularBuffer;
+
+use crate::state::Scene;
+
+// number of steps kept for rewinding, older ones are dropped
+pub const HISTORY_LENGTH: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    // number of steps taken to reach this frame
+    pub step: u64,
+    pub t: f32,
+    pub scene: Scene,
+}
+
+// Fixed size history of world snapshots, one per step. `cursor` is the frame currently shown; recording
+// while the cursor is not on the newest frame throws away the frames after it, so resuming from a past
+// frame starts a new branch of the timeline.
+pub struct Timeline {
+    frames: Box<CircularBuffer<HISTORY_LENGTH, Frame>>,
+    cursor: usize,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self {
+            frames: CircularBuffer::boxed(),
+            cursor: 0,
+        }
+    }
+
+    pub fn record(&mut self, step: u64, t: f32, scene: &Scene) {
+        if !self.frames.is_empty() {
+            self.frames.truncate_back(self.cursor + 1);
+        }
+        self.frames.push_back(Frame {
+            step,
+            t,
+            scene: scene.clone(),
+        });
+        self.cursor = self.frames.len() - 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.cursor = 0;
+    }
+
+    pub fn step_back(&mut self) -> Option<&Frame> {
+        self.seek(self.cursor.saturating_sub(1))
+    }
+
+    pub fn step_forward(&mut self) -> Option<&Frame> {
+        self.seek(self.cursor + 1)
+    }
+
+    pub fn seek(&mut self, index: usize) -> Option<&Frame> {
+        if self.frames.is_empty() {
+            return None;
+        }
+        self.cursor = index.min(self.frames.len() - 1);
+        self.frames.get(self.cursor)
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+}
@@ -0,0 +1,170 @@
+use nalgebra::{vector, Vector2};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constraints::Constraint,
+    joints::{Motor, WheelJoint},
+    shapes::{Ball, Shape},
+    sleep,
+    state::Scene,
+};
+
+// How a vehicle is being driven, by the arrow keys or by the scene file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Drive {
+    // from -1, full reverse, to 1, full ahead (to the right)
+    #[serde(default)]
+    pub throttle: f32,
+    #[serde(default)]
+    pub brake: bool,
+}
+
+// A chassis ball and the wheels hung from it, which are the balls held by wheel joints on the chassis.
+// The engine drives every wheel towards `top_speed` times the throttle (in rad/s, relative to the chassis) with
+// at most `torque` N·m, and the brakes stop them turning with at most `brake_torque`. With neither the wheels
+// roll freely.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Vehicle {
+    #[serde(default)]
+    pub name: String,
+    pub chassis: usize,
+    pub top_speed: f32,
+    pub torque: f32,
+    pub brake_torque: f32,
+    // how it is driven while no arrow key is held, so a scene can drive itself in a headless run
+    #[serde(default)]
+    pub drive: Drive,
+    // from the arrow keys while one is held, in place of `drive`
+    #[serde(skip)]
+    pub input: Option<Drive>,
+}
+
+impl Vehicle {
+    fn motor(&self, drive: Drive) -> Option<Motor> {
+        if drive.brake {
+            Some(Motor {
+                speed: 0.,
+                max_force: self.brake_torque,
+            })
+        } else if drive.throttle != 0. {
+            Some(Motor {
+                speed: self.top_speed * drive.throttle.clamp(-1., 1.),
+                max_force: self.torque,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+// Sets the motors of every vehicle's wheels for the next step, called at the start of each step. A parked
+// vehicle falls asleep like anything else, and wakes up when the throttle is opened.
+pub fn update(scene: &mut Scene) {
+    let mut woken = Vec::new();
+    for vehicle in &scene.vehicles {
+        let drive = vehicle.input.unwrap_or(vehicle.drive);
+        let motor = vehicle.motor(drive);
+        for constraint in &mut scene.constraints {
+            if let Constraint::Wheel(joint) = constraint {
+                if joint.index_1 == vehicle.chassis {
+                    joint.motor = motor;
+                }
+            }
+        }
+        if drive.throttle != 0. && !drive.brake {
+            woken.push(vehicle.chassis);
+        }
+    }
+    for chassis in woken {
+        sleep::wake(scene, chassis);
+    }
+}
+
+// Puts together a vehicle: a chassis ball with a wheel hanging from each of `wheels`, offsets from the
+// chassis's centre to where each wheel's suspension holds it
+#[derive(Debug, Clone)]
+pub struct VehicleBuilder {
+    pub chassis_mass: f32,
+    pub chassis_radius: f32,
+    pub wheels: Vec<Vector2<f32>>,
+    pub wheel_mass: f32,
+    pub wheel_radius: f32,
+    pub wheel_friction: f32,
+    // suspension spring, damper and how far it can travel either way
+    pub stiffness: f32,
+    pub damping: f32,
+    pub travel: f32,
+    pub top_speed: f32,
+    pub torque: f32,
+    pub brake_torque: f32,
+}
+
+impl VehicleBuilder {
+    // a car about 3 m long with a wheel at each end
+    pub fn new_default() -> Self {
+        Self {
+            chassis_mass: 8.,
+            chassis_radius: 0.5,
+            wheels: vec![vector![-1.2, 0.7], vector![1.2, 0.7]],
+            wheel_mass: 1.5,
+            wheel_radius: 0.45,
+            wheel_friction: 0.9,
+            stiffness: 400.,
+            damping: 30.,
+            travel: 0.3,
+            top_speed: 15.,
+            torque: 30.,
+            brake_torque: 60.,
+        }
+    }
+
+    // adds the vehicle to `scene` with its chassis at `position`, returning its index in `scene.vehicles`
+    pub fn build(&self, scene: &mut Scene, position: Vector2<f32>) -> usize {
+        let chassis = scene.shapes.len();
+        scene.shapes.push(Shape::Ball(Ball::new(
+            position,
+            vector![0., 0.],
+            vector![0., 0.],
+            vector![0., 0.],
+            self.chassis_mass,
+            self.chassis_radius,
+            0.2,
+            0.5,
+        )));
+        for offset in &self.wheels {
+            let mut wheel = Ball::new(
+                position + offset,
+                vector![0., 0.],
+                vector![0., 0.],
+                vector![0., 0.],
+                self.wheel_mass,
+                self.wheel_radius,
+                0.1,
+                self.wheel_friction,
+            );
+            wheel.rolls = true;
+            scene.constraints.push(Constraint::Wheel(WheelJoint {
+                index_0: scene.shapes.len(),
+                index_1: chassis,
+                anchor_1: *offset,
+                axis: vector![0., 1.],
+                stiffness: self.stiffness,
+                damping: self.damping,
+                limits: Some((-self.travel, self.travel)),
+                motor: None,
+                collide_connected: false,
+            }));
+            scene.shapes.push(Shape::Ball(wheel));
+        }
+        scene.vehicles.push(Vehicle {
+            name: String::new(),
+            chassis,
+            top_speed: self.top_speed,
+            torque: self.torque,
+            brake_torque: self.brake_torque,
+            drive: Drive::default(),
+            input: None,
+        });
+        scene.vehicles.len() - 1
+    }
+}
@@ -0,0 +1,640 @@
+use std::collections::HashSet;
+
+use macroquad::color::{BLACK, BLUE, WHITE};
+use nalgebra::{vector, Vector2};
+
+use crate::{
+    constraints::{Constraint, ConstraintUpdate},
+    emitters, fluid, gas, langevin,
+    physics::{
+        collision_position_delta, coulomb_friction_impulse, elastic_collision_velocity,
+        wall_collision_velocity, Collision, ForceGenerator, MouseJoint, ObjectForceGenerator,
+    },
+    shapes::{
+        ball_ball_collision, ball_line_collision, ball_point_collision, layers_collide,
+        line_line_collision, point_line_distance, Ball, Shape,
+    },
+    sleep,
+    solver::{semi_implicit_euler, EntityState, RungeKuttaIntegrator, TimeIntegrator},
+    state::Scene,
+    vehicle,
+};
+
+// What a step did that the caller needs to keep anything indexed by shape in line with the scene
+pub struct StepOutput {
+    // the collisions that were resolved, one per ball involved and substep
+    pub collisions: Vec<(usize, Collision)>,
+    // shapes removed by emitters at the start of the step, in order, each index as it was just before that
+    // shape went
+    pub removed: Vec<usize>,
+}
+
+// Advances the scene by one step of `integrator.dt()`, split into `settings.substeps` substeps: in each one
+// collisions are resolved first, then constraints, then forces are integrated. Emitters add and remove their
+// particles and vehicles set their motors before the first substep. Input and rendering are left to the caller.
+pub fn step(scene: &mut Scene, integrator: &RungeKuttaIntegrator, t: f32) -> StepOutput {
+    let removed = emitters::update(scene, integrator.dt());
+    vehicle::update(scene);
+    let substeps = scene.settings.substeps.max(1);
+    let substep_integrator = RungeKuttaIntegrator::new(integrator.dt() / substeps as f32);
+    let dt = substep_integrator.dt();
+    let mut collisions = Vec::new();
+    for i in 0..substeps {
+        collisions.extend(substep(scene, &substep_integrator, t + i as f32 * dt));
+    }
+    StepOutput {
+        collisions,
+        removed,
+    }
+}
+
+fn substep(
+    scene: &mut Scene,
+    integrator: &RungeKuttaIntegrator,
+    t: f32,
+) -> Vec<(usize, Collision)> {
+    let dt = integrator.dt();
+    let mut forces = collect_forces(scene);
+    for force in langevin::forces(scene, dt) {
+        forces[force.entity_idx].push(Box::new(force));
+    }
+    wake_on_force_change(scene, &forces);
+
+    let mut collisions = find_collisions(scene);
+    apply_collisions(scene, &collisions);
+    gas::thermalize(scene, &mut collisions);
+
+    let mut updates = solve_constraints(scene, dt);
+    apply_constraint_updates(scene, &updates);
+    // Springs only change velocities and rods and pins only positions. The extra passes only redo the
+    // position corrections, since applying a spring twice would make it twice as stiff.
+    for _ in 1..scene.settings.iterations.max(1) {
+        updates = solve_constraints(scene, dt);
+        for update in &mut updates {
+            update.velocity_update = vector![0., 0.];
+            update.angular_velocity_update = 0.;
+        }
+        apply_constraint_updates(scene, &updates);
+    }
+
+    integrate(scene, integrator, &forces, t);
+
+    sleep::update_sleep(scene, &collisions, dt);
+    collisions
+}
+
+// Drags the grabbed ball towards `mouse`, the cursor position while the left button is held, through a
+// `MouseJoint`. Pressing on a ball grabs it, releasing the button lets go.
+pub fn drag(scene: &mut Scene, mouse: Option<Vector2<f32>>) {
+    let mpoint = match mouse {
+        Some(mpoint) => mpoint,
+        None => {
+            if let Some(joint) = scene.mouse_joint.take() {
+                if let Shape::Ball(ball) = &mut scene.shapes[joint.entity_idx] {
+                    ball.clicked = false;
+                    ball.color = WHITE;
+                }
+            }
+            return;
+        }
+    };
+    if let Some(joint) = &mut scene.mouse_joint {
+        joint.target = mpoint;
+        return;
+    }
+    let grabbed = scene.shapes.iter().position(
+        |shape| matches!(shape, Shape::Ball(ball) if ball_point_collision(ball, &mpoint, ball.radius)),
+    );
+    if let Some(i) = grabbed {
+        if let Shape::Ball(ball) = &mut scene.shapes[i] {
+            ball.clicked = true;
+            ball.color = BLACK;
+            scene.mouse_joint = Some(MouseJoint::new(
+                i,
+                mpoint - ball.position,
+                mpoint,
+                ball.mass,
+            ));
+        }
+        sleep::wake(scene, i);
+    }
+}
+
+fn net_force(ball: &Ball, forces: &[Box<dyn ForceGenerator>]) -> Vector2<f32> {
+    let state = EntityState {
+        velocity: ball.velocity,
+        position: ball.position,
+        mass: ball.mass,
+    };
+    forces
+        .iter()
+        .fold(vector![0., 0.], |net, force| force.accumulate(&state, &net))
+}
+
+// A sleeping ball keeps the net force it had when it fell asleep, so a force generator being added, removed
+// or changed shows up as a difference
+fn wake_on_force_change(scene: &mut Scene, forces: &[Vec<Box<dyn ForceGenerator>>]) {
+    let mut changed = Vec::new();
+    for (i, shape) in scene.shapes.iter().enumerate() {
+        if let Shape::Ball(ball) = shape {
+            if ball.sleeping {
+                let force = net_force(ball, &forces[i]);
+                if (force - ball.force).magnitude() > 1e-3 * ball.force.magnitude().max(1.) {
+                    changed.push(i);
+                }
+            }
+        }
+    }
+    for i in changed {
+        sleep::wake(scene, i);
+    }
+}
+
+// forces acting on each shape, indexed like `scene.shapes`
+pub fn collect_forces(scene: &Scene) -> Vec<Vec<Box<dyn ForceGenerator>>> {
+    let mut forces: Vec<Vec<Box<dyn ForceGenerator>>> =
+        scene.shapes.iter().map(|_| Vec::new()).collect();
+
+    if let Some(joint) = &scene.mouse_joint {
+        forces[joint.entity_idx].push(Box::new(joint.clone()));
+    }
+
+    for force in &scene.forces {
+        let idx = force.entity_idx();
+        let active = match &scene.shapes[idx] {
+            Shape::Ball(ball) => force
+                .sensors()
+                .iter()
+                .all(|&sensor| scene.sensors[sensor].detects(ball)),
+            Shape::Line(_) => true,
+        };
+        if active {
+            forces[idx].push(force.generator());
+        }
+    }
+
+    for (i, force) in fluid::forces(scene) {
+        // left unnormalised so the whole force is applied
+        forces[i].push(Box::new(ObjectForceGenerator {
+            strength: 1.,
+            direction: force,
+            entity_idx: i,
+        }));
+    }
+
+    for (i, shape) in scene.shapes.iter().enumerate() {
+        if let Shape::Ball(ball) = shape {
+            if scene.settings.gravity != vector![0., 0.] {
+                // an acceleration, so the force scales with the mass
+                let gravity = ObjectForceGenerator::new(
+                    ball.mass * scene.settings.gravity.magnitude(),
+                    scene.settings.gravity,
+                    i,
+                );
+                forces[i].push(Box::new(gravity));
+            }
+        }
+    }
+    forces
+}
+
+// walls never move and sleeping balls don't until something wakes them
+fn is_resting(shape: &Shape) -> bool {
+    match shape {
+        Shape::Ball(ball) => ball.sleeping,
+        Shape::Line(_) => true,
+    }
+}
+
+pub fn find_collisions(scene: &Scene) -> Vec<(usize, Collision)> {
+    let mut collisions: Vec<(usize, Collision)> = Vec::new();
+    let ignored: HashSet<(usize, usize)> = scene
+        .constraints
+        .iter()
+        .filter_map(Constraint::ignored_pair)
+        .collect();
+
+    for (i, obj1) in scene.shapes.iter().enumerate() {
+        for (j, obj2) in scene.shapes[i + 1..].iter().enumerate() {
+            let shapes_j_index = i + j + 1;
+            if is_resting(obj1) && is_resting(obj2) {
+                // nothing here can move, including sleeping balls lying on a wall
+                continue;
+            }
+            if !layers_collide(obj1, obj2) || ignored.contains(&(i, shapes_j_index)) {
+                continue;
+            }
+            if let (Shape::Ball(ball1), Shape::Ball(ball2)) = (obj1, obj2) {
+                // pressure keeps fluid particles apart instead
+                if ball1.fluid && ball2.fluid {
+                    continue;
+                }
+            }
+            // collision logic
+            match (obj1, obj2) {
+                (Shape::Ball(ball1), Shape::Ball(ball2)) => {
+                    // ball 1 is i; ball2 is shapes_j_index
+                    if ball_ball_collision(ball1, ball2) {
+                        // NOTE: it might be better to calculate everything here and have Collision store only the final values
+                        // Then in application loop, all that happens is that ball is translated by pos stored in Collision
+                        // And ball velocity is set to whatever is stored in Collision
+                        // It would simplify Collision
+                        let m_a = ball1.mass;
+                        let m_b = ball2.mass;
+
+                        let vi_a = ball1.velocity;
+                        let vi_b = ball2.velocity;
+
+                        let material = scene.materials.pair(obj1, obj2);
+
+                        // used for normal and for position transform
+                        let d = ball2.position - ball1.position;
+                        let distance = d.magnitude();
+                        if distance < 1e-6 {
+                            continue;
+                        } // Avoid division by zero or very small distances
+                        let total_radius = ball1.radius + ball2.radius;
+                        let collision_depth = total_radius - distance;
+                        let normal = d / distance;
+                        // as for walls, slow contacts don't bounce so stacked balls can come to rest
+                        let c_r =
+                            if (vi_a - vi_b).dot(&normal) < scene.settings.restitution_threshold {
+                                0.
+                            } else {
+                                material.elasticity
+                            };
+
+                        let mass_sum = m_a + m_b;
+                        let translate_by = collision_position_delta(normal, collision_depth);
+
+                        // Tranlsations are prop. to the masses of the balls - i.e when equal, is equivalnet to just dividing translation equally.
+                        let translate_by_a = (translate_by * (m_b / mass_sum));
+                        let translate_by_b = (translate_by * (m_a / mass_sum));
+
+                        let (mut vf_a, mut vf_b) =
+                            elastic_collision_velocity(ball1, ball2, normal, c_r);
+
+                        // Coulomb friction on whatever sliding of the contact points is left after the
+                        // normal response. Only balls that roll spin, for the rest it just slows the sliding
+                        let normal_impulse = (m_a * (vf_a - vi_a)).dot(&normal).abs();
+                        let (arm_a, arm_b) = (normal * ball1.radius, -normal * ball2.radius);
+                        let (ii_a, ii_b) = (
+                            ball1.contact_inverse_inertia(),
+                            ball2.contact_inverse_inertia(),
+                        );
+                        let v_rel = (vf_a + ball1.angular_velocity * vector![-arm_a.y, arm_a.x])
+                            - (vf_b + ball2.angular_velocity * vector![-arm_b.y, arm_b.x]);
+                        let friction_impulse = coulomb_friction_impulse(
+                            v_rel - normal * v_rel.dot(&normal),
+                            normal_impulse,
+                            1. / m_a
+                                + 1. / m_b
+                                + ii_a * ball1.radius * ball1.radius
+                                + ii_b * ball2.radius * ball2.radius,
+                            material.static_friction,
+                            material.friction,
+                        );
+                        vf_a += friction_impulse / m_a;
+                        vf_b -= friction_impulse / m_b;
+
+                        let point = ball1.position + normal * (ball1.radius - collision_depth / 2.);
+                        let collision_1 = Collision::new(-translate_by_a, vf_a, shapes_j_index)
+                            .at(point, normal, collision_depth)
+                            .with_impulse(m_a * (vf_a - vi_a))
+                            .with_spin(ii_a * arm_a.perp(&friction_impulse));
+                        let collision_2 = Collision::new(translate_by_b, vf_b, i)
+                            .at(point, -normal, collision_depth)
+                            .with_impulse(m_b * (vf_b - vi_b))
+                            .with_spin(-ii_b * arm_b.perp(&friction_impulse));
+
+                        collisions.push((i, collision_1));
+                        collisions.push((shapes_j_index, collision_2));
+                    }
+                }
+                (Shape::Ball(ball), Shape::Line(line)) => {
+                    if ball_line_collision(ball, line) {
+                        // println!("Collision detected between ball and line!");
+                        let mut normal = line.normal();
+                        let ball_to_line = line.start_point - ball.position;
+                        let material = scene.materials.pair(obj1, obj2);
+
+                        if ball_to_line.dot(&normal) < 0.0 {
+                            // If the normal is facing the wrong way, flip it
+                            normal = -normal;
+                        }
+
+                        // Calculate the collision depth
+                        let collision_depth =
+                            ball.radius - point_line_distance(&line, &ball.position);
+
+                        // If collision depth is positive, calculate the translation vector to separate them
+                        if collision_depth > 0. {
+                            let translate_by_ball =
+                                -collision_position_delta(normal, collision_depth);
+
+                            // Compute the final velocity after collision
+                            let (vf_ball, spin) = wall_collision_velocity(
+                                normal,
+                                material.elasticity,
+                                material.static_friction,
+                                material.friction,
+                                scene.settings.restitution_threshold,
+                                ball,
+                            );
+
+                            // Create a collision object to store translation and velocity updates for the ball
+                            let collision =
+                                Collision::new(translate_by_ball, vf_ball, shapes_j_index)
+                                    .at(
+                                        ball.position
+                                            + normal.normalize() * (ball.radius - collision_depth),
+                                        normal,
+                                        collision_depth,
+                                    )
+                                    .with_impulse(ball.mass * (vf_ball - ball.velocity))
+                                    .with_spin(spin);
+
+                            // Add collision object for the ball
+                            collisions.push((i, collision));
+                        }
+                    }
+                }
+                (Shape::Line(line), Shape::Ball(ball)) => {
+                    if ball_line_collision(ball, line) {
+                        // Calculate collision normal (line's normal direction)
+                        let mut normal = line.normal();
+                        let ball_to_line = line.start_point - ball.position;
+                        let material = scene.materials.pair(obj1, obj2);
+
+                        if ball_to_line.dot(&normal) > 0.0 {
+                            // If the normal is facing the wrong way, flip it
+                            normal = -normal;
+                        }
+                        // Calculate the collision depth
+                        let collision_depth =
+                            ball.radius - point_line_distance(&line, &ball.position);
+
+                        // If collision depth is positive, calculate the translation vector to separate them
+                        if collision_depth > 0. {
+                            let translate_by_ball =
+                                collision_position_delta(normal, collision_depth);
+
+                            // Compute the final velocity after collision, `normal` points away from the
+                            // wall here
+                            let (vf_ball, spin) = wall_collision_velocity(
+                                -normal,
+                                material.elasticity,
+                                material.static_friction,
+                                material.friction,
+                                scene.settings.restitution_threshold,
+                                ball,
+                            );
+
+                            // Create a collision object to store translation and velocity updates for the ball
+                            let collision = Collision::new(translate_by_ball, vf_ball, i)
+                                .at(
+                                    ball.position
+                                        - normal.normalize() * (ball.radius - collision_depth),
+                                    -normal,
+                                    collision_depth,
+                                )
+                                .with_impulse(ball.mass * (vf_ball - ball.velocity))
+                                .with_spin(spin);
+
+                            // Add collision object for the ball
+                            collisions.push((shapes_j_index, collision)); // Note the different index here
+                        }
+                    }
+                }
+                (Shape::Line(line1), Shape::Line(line2)) => {
+                    if line_line_collision(line1, line2) {
+                        // Handle collision
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    collisions
+}
+
+pub fn apply_collisions(scene: &mut Scene, collisions: &[(usize, Collision)]) {
+    for (i, collision) in collisions {
+        match (&mut scene.shapes[*i]) {
+            (Shape::Ball(ball)) => {
+                // ball.translate_by(10. * unit);
+                // if &ball.velocity.dot(&unit) < &0. {
+                //     ball.velocity = ball.velocity - 2. * *value;
+                // }
+
+                // TODO: add properties to each object for elasticity and friction coeff. so that this can be updated, and add these to Collision
+                // TODO: change these function names to not be wall but just general collision since its not only wall
+                let pos_delta = collision.translate_by;
+
+                // TODO: remove elasticity from collision, should just be property of lines and balls
+                ball.translate_by(pos_delta);
+
+                let ball_vel = collision.vf;
+
+                ball.velocity = ball_vel;
+                ball.angular_velocity += collision.spin;
+            }
+
+            (Shape::Line(line)) => {
+                // println!("match line");
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn solve_constraints(scene: &Scene, dt: f32) -> Vec<ConstraintUpdate> {
+    let mut updates: Vec<ConstraintUpdate> = Vec::new();
+    for constraint in &scene.constraints {
+        if constraint
+            .indices()
+            .iter()
+            .all(|&i| sleep::is_sleeping(scene, i))
+        {
+            continue;
+        }
+        match constraint {
+            (Constraint::Spring(constraint)) => {
+                if let (Shape::Ball(ball1), Shape::Ball(ball2)) = (
+                    &scene.shapes[constraint.index_0],
+                    &scene.shapes[constraint.index_1],
+                ) {
+                    // Calculate the delta between ball positions
+                    let p0 = ball1.position;
+                    let p1 = ball2.position;
+                    let v0 = ball1.velocity;
+                    let v1 = ball2.velocity;
+
+                    let delta = p1 - p0;
+                    let distance = delta.magnitude();
+                    let direction = delta / distance;
+
+                    let required_delta = direction * constraint.distance;
+                    let force = constraint.k * (required_delta - delta);
+
+                    let mut update_1 = ConstraintUpdate::default();
+                    let mut update_2 = ConstraintUpdate::default();
+
+                    // let force = ObjectForceGenerator {
+                    //     strength: force.magnitude(),
+                    //     direction: force.normalize(),
+                    //     entity_idx: constraint.index_0,
+                    // };
+
+                    // let force2 = ObjectForceGenerator {
+                    //     strength: force.magnitude(),
+                    //     direction: force.normalize(),
+                    //     entity_idx: constraint.index_0,
+                    // };
+
+                    // Update velocities due to spring force
+                    update_1.velocity_update = -force * dt / ball1.mass;
+                    update_2.velocity_update = force * dt / ball2.mass;
+
+                    // Calculate relative velocity along the direction of the spring
+                    let vrel = (v1 - v0).dot(&direction);
+
+                    // Apply damping factor
+                    let damping_factor = (-constraint.k * dt).exp();
+                    let new_vrel = vrel * damping_factor;
+                    let vrel_delta = new_vrel - vrel;
+                    let vrel_delta_vec = vrel_delta * direction;
+
+                    // Update velocities due to damping
+                    update_1.velocity_update += -vrel_delta_vec / 2.0;
+                    update_2.velocity_update += vrel_delta_vec / 2.0;
+
+                    update_1.index = constraint.index_0;
+                    update_2.index = constraint.index_1;
+
+                    // let force = SpringForceGenerator {
+                    //     b: constraint.dampen,
+                    //     k: constraint.k,
+                    //     length: constraint.distance,
+                    //     displacement: delta / 2.,
+                    //     entity_idx: constraint.index_0,
+                    // };
+
+                    // let force2 = SpringForceGenerator {
+                    //     b: constraint.dampen,
+                    //     k: constraint.k,
+                    //     length: constraint.distance,
+                    //     displacement: -delta / 2.,
+                    //     entity_idx: constraint.index_1,
+                    // };
+
+                    // forces.push(Box::new(force));
+                    // forces.push(Box::new(force2));
+                    updates.push(update_1);
+                    updates.push(update_2);
+                }
+            }
+            (Constraint::Distance(constraint)) => {
+                if let (Shape::Ball(ball1), Shape::Ball(ball2)) = (
+                    &scene.shapes[constraint.index_0],
+                    &scene.shapes[constraint.index_1],
+                ) {
+                    let delta = ball2.position - ball1.position;
+                    let total_correction = delta.magnitude() - constraint.distance;
+                    let norm = delta.normalize();
+                    let offset = norm * total_correction;
+
+                    let mut update_1 = ConstraintUpdate::default();
+                    let mut update_2 = ConstraintUpdate::default();
+
+                    update_1.position_update = offset / 2.;
+                    update_2.position_update = -offset / 2.;
+
+                    update_1.index = constraint.index_0;
+                    update_2.index = constraint.index_1;
+
+                    updates.push(update_1);
+                    updates.push(update_2);
+                }
+            }
+            Constraint::Revolute(joint) => updates.extend(joint.solve(&scene.shapes, dt)),
+            Constraint::Prismatic(joint) => updates.extend(joint.solve(&scene.shapes, dt)),
+            Constraint::Wheel(joint) => updates.extend(joint.solve(&scene.shapes, dt)),
+            (Constraint::FixedPoint(constraint)) => {
+                if let (Shape::Ball(ball)) = &scene.shapes[constraint.index] {
+                    let delta = ball.position - constraint.position;
+                    let mut update = ConstraintUpdate::default();
+                    update.position_update = -delta;
+                    update.index = constraint.index;
+                    updates.push(update);
+                }
+            }
+        }
+    }
+
+    updates
+}
+
+pub fn apply_constraint_updates(scene: &mut Scene, updates: &[ConstraintUpdate]) {
+    for update in updates {
+        let shape = &mut scene.shapes[update.index];
+        match shape {
+            Shape::Ball(ball) => {
+                ball.position += update.position_update;
+                ball.color = BLUE;
+                ball.velocity += update.velocity_update;
+                ball.angle += update.angle_update;
+                ball.angular_velocity += update.angular_velocity_update;
+                // ball.force += update.force_update;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn integrate(
+    scene: &mut Scene,
+    integrator: &RungeKuttaIntegrator,
+    forces: &[Vec<Box<dyn ForceGenerator>>],
+    t: f32,
+) {
+    for (idx, shape) in scene.shapes.iter_mut().enumerate() {
+        // TODO: have trait of shapes be that they have EntityState, then for line make start pos and end pos a fn and have line be defined
+        // normally with 'x' in entity state (pos), and then a norm vector and a length, so x is start point, norm is direction of line
+        // for v just always 0 uness implement v for lines
+
+        // TODO: set velocity to 0 if very small
+        if let Shape::Ball(ball) = shape {
+            if ball.sleeping {
+                continue;
+            }
+            let state = EntityState {
+                velocity: ball.velocity,
+                position: ball.position,
+                mass: ball.mass,
+            };
+
+            // kept on the ball so that it can be inspected or exported after the step
+            ball.force = net_force(ball, &forces[idx]);
+            ball.acceleration = ball.force / ball.mass;
+
+            let (x_update, v_update) = match scene.settings.integrator {
+                TimeIntegrator::RungeKutta4 => integrator.integrate(&state, &forces[idx], t),
+                TimeIntegrator::SemiImplicitEuler => {
+                    semi_implicit_euler(&state, &forces[idx], integrator.dt())
+                }
+            };
+            // println!("x update {:?}", x_update);
+            // println!("v update {:?}", v_update);
+
+            ball.velocity += v_update;
+            ball.position += x_update;
+            ball.angle += ball.angular_velocity * integrator.dt();
+            if scene.settings.damping > 0. {
+                ball.velocity *= (-scene.settings.damping * integrator.dt()).exp();
+                ball.angular_velocity *= (-scene.settings.damping * integrator.dt()).exp();
+            }
+        };
+    }
+}